@@ -0,0 +1,90 @@
+use crate::common::{
+    Checksum, ErrorChecksum, ErrorCompressedSizeTooBig, ErrorLz4WrongDecompressedSize, Result,
+};
+use crate::compression::Compression;
+use crate::lz4_block_header::{CompressionMethod, Lz4BlockHeader};
+
+/// Everything needed to turn the data of a block held in memory back into the decompressed data.
+#[derive(Debug)]
+pub(crate) struct BlockDecoder<C: Compression> {
+    compression: C,
+    checksum: Checksum,
+    accept_unmasked_checksum: bool,
+}
+
+impl<C: Compression> BlockDecoder<C> {
+    pub(crate) fn new(c: C, checksum: Checksum) -> Self {
+        Self {
+            compression: c,
+            checksum,
+            accept_unmasked_checksum: false,
+        }
+    }
+
+    /// Also accept the checksums keeping the 4 first bits dropped by the Java implementation,
+    /// when they are the full xxhash32 of the data.
+    pub(crate) fn set_accept_unmasked_checksum(&mut self, accept_unmasked_checksum: bool) {
+        self.accept_unmasked_checksum = accept_unmasked_checksum;
+    }
+
+    /// Decompress the data following `header` into `decompressed_buf`, and check it.
+    ///
+    /// `data` must have the compressed length and `decompressed_buf` the decompressed length from the header.
+    /// It returns what [`Self::check()`] returns.
+    pub(crate) fn decode_into(
+        &self,
+        header: &Lz4BlockHeader,
+        data: &[u8],
+        decompressed_buf: &mut [u8],
+    ) -> Result<bool> {
+        match header.compression_method {
+            CompressionMethod::Raw => decompressed_buf.copy_from_slice(data),
+            CompressionMethod::Lz4 => match self.compression.decompress(data, decompressed_buf) {
+                Ok(s) if s != decompressed_buf.len() => {
+                    return ErrorLz4WrongDecompressedSize::new_error(s, decompressed_buf.len());
+                }
+                Ok(_) => {}
+                Err(err) => return Err(err.into()),
+            },
+        }
+        self.check(header, decompressed_buf)
+    }
+
+    /// Check the decompressed data against the checksum from `header`.
+    ///
+    /// It returns `true` if the checksum is only accepted as unmasked, see [`Self::set_accept_unmasked_checksum()`].
+    pub(crate) fn check(&self, header: &Lz4BlockHeader, decompressed_buf: &[u8]) -> Result<bool> {
+        let computed_checksum = self.checksum.run(decompressed_buf);
+        if computed_checksum == header.checksum {
+            return Ok(false);
+        }
+        if self.accept_unmasked_checksum
+            && header.checksum == Lz4BlockHeader::unmasked_checksum(decompressed_buf)
+        {
+            return Ok(true);
+        }
+        ErrorChecksum::new_error(header.checksum, computed_checksum)
+    }
+
+    /// Check that the compressed length from `header` is not bigger than what the [`Compression`] can produce
+    /// for its decompressed length.
+    ///
+    /// A header can claim up to `i32::MAX` bytes, so it must be checked before gathering the data in memory.
+    /// Every decoder applies this rule, so they accept the same streams. The Java implementation never writes such blocks.
+    pub(crate) fn check_compressed_len(&self, header: &Lz4BlockHeader) -> Result<()> {
+        let max_compressed_len =
+            self.get_maximum_compressed_buffer_len(header.decompressed_len as usize);
+        if header.compressed_len as usize > max_compressed_len {
+            return ErrorCompressedSizeTooBig::new_error(
+                header.compressed_len,
+                max_compressed_len as u32,
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize {
+        self.compression
+            .get_maximum_compressed_buffer_len(decompressed_len)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::block_strategy::{BlockStrategy, SmallestBlock};
+use crate::common::{Checksum, ErrorInternal, Result};
+use crate::compression::Compression;
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader};
+
+use std::io::Write;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// Compress a block into `scratch` if the strategy asks for it.
+///
+/// It returns the header and the data to write after it, which is either `block` or a part of `scratch`.
+pub(crate) fn encode_block<'b, C: Compression>(
+    compression: &C,
+    strategy: &dyn BlockStrategy,
+    checksum: &Checksum,
+    compression_level: CompressionLevel,
+    block: &'b [u8],
+    scratch: &'b mut [u8],
+) -> Result<(Lz4BlockHeader, &'b [u8])> {
+    let (compression_method, buf_to_write) = match strategy.select(block) {
+        Some(CompressionMethod::Raw) => (CompressionMethod::Raw, block),
+        selected => {
+            let compressed_buf = match compression.compress(block, scratch) {
+                Ok(s) => &scratch[..s],
+                Err(err) => return Err(err.into()),
+            };
+            if selected == Some(CompressionMethod::Lz4)
+                || strategy.accept_compressed(block.len(), compressed_buf.len())
+            {
+                (CompressionMethod::Lz4, compressed_buf)
+            } else {
+                (CompressionMethod::Raw, block)
+            }
+        }
+    };
+    Ok((
+        Lz4BlockHeader {
+            compression_method,
+            compression_level,
+            compressed_len: buf_to_write.len() as u32,
+            decompressed_len: block.len() as u32,
+            checksum: checksum.run(block),
+        },
+        buf_to_write,
+    ))
+}
+
+// BlockEncoder
+
+/// Everything needed to turn a decompressed block into a header and its data.
+#[derive(Debug)]
+pub(crate) struct BlockEncoder<C: Compression> {
+    compression: C,
+    compression_level: CompressionLevel,
+    checksum: Checksum,
+    strategy: Box<dyn BlockStrategy>,
+    compressed_buf: Vec<u8>,
+}
+
+impl<C: Compression> BlockEncoder<C> {
+    pub(crate) fn new(c: C, compression_level: CompressionLevel, checksum: Checksum) -> Self {
+        let compressed_buf_len = c
+            .get_maximum_compressed_buffer_len(compression_level.get_max_decompressed_buffer_len());
+        Self {
+            compression: c,
+            compression_level,
+            checksum,
+            strategy: Box::new(SmallestBlock),
+            compressed_buf: vec![0u8; compressed_buf_len],
+        }
+    }
+
+    /// Get the maximum length of the data written after the header of a block of `len` bytes.
+    pub(crate) fn get_maximum_encoded_len(&self, len: usize) -> usize {
+        self.compression.get_maximum_compressed_buffer_len(len)
+    }
+
+    pub(crate) fn set_strategy(&mut self, strategy: Box<dyn BlockStrategy>) {
+        self.strategy = strategy;
+    }
+
+    /// Compress a block into the compression buffer if needed, see [`encode_block()`].
+    pub(crate) fn encode<'b>(&'b mut self, block: &'b [u8]) -> Result<(Lz4BlockHeader, &'b [u8])> {
+        encode_block(
+            &self.compression,
+            self.strategy.as_ref(),
+            &self.checksum,
+            self.compression_level,
+            block,
+            self.compressed_buf.as_mut(),
+        )
+    }
+
+    pub(crate) fn encode_into<W: Write>(
+        &mut self,
+        block: &[u8],
+        writer: &mut W,
+    ) -> Result<Lz4BlockHeader> {
+        let (header, buf_to_write) = self.encode(block)?;
+        header.write(writer)?;
+        writer.write_all(buf_to_write)?;
+        Ok(header)
+    }
+}
+
+// BlockPipeline
+
+#[derive(Debug)]
+enum Job {
+    Block {
+        block: Vec<u8>,
+        len: usize,
+        out: Vec<u8>,
+    },
+    Strategy(Box<dyn BlockStrategy>),
+}
+
+#[derive(Debug)]
+struct Encoded {
+    block: Vec<u8>,
+    out: Vec<u8>,
+    header: Lz4BlockHeader,
+}
+
+/// Run a [`BlockEncoder`] on a background thread, one block at a time.
+///
+/// The encoded blocks are written by the caller's thread, as the wrapped [`Write`] is borrowed.
+#[derive(Debug)]
+pub(crate) struct BlockPipeline<C: Compression> {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Result<Encoded>>,
+    handle: Option<JoinHandle<BlockEncoder<C>>>,
+    in_flight: bool,
+    spare_block: Option<Vec<u8>>,
+    spare_out: Vec<u8>,
+}
+
+impl<C: Compression + Send + 'static> BlockPipeline<C> {
+    pub(crate) fn spawn(mut encoder: BlockEncoder<C>) -> Self {
+        let (jobs, jobs_receiver) = channel::<Job>();
+        let (results_sender, results) = channel();
+        let handle = thread::spawn(move || {
+            for job in jobs_receiver {
+                match job {
+                    Job::Block {
+                        block,
+                        len,
+                        mut out,
+                    } => {
+                        out.clear();
+                        let result = encoder
+                            .encode_into(&block[..len], &mut out)
+                            .map(|header| Encoded { block, out, header });
+                        if results_sender.send(result).is_err() {
+                            break;
+                        }
+                    }
+                    Job::Strategy(strategy) => encoder.set_strategy(strategy),
+                }
+            }
+            encoder
+        });
+        Self {
+            jobs: Some(jobs),
+            results,
+            handle: Some(handle),
+            in_flight: false,
+            spare_block: None,
+            spare_out: Vec::new(),
+        }
+    }
+}
+
+impl<C: Compression> BlockPipeline<C> {
+    fn send(&self, job: Job) -> Result<()> {
+        match &self.jobs {
+            Some(jobs) if jobs.send(job).is_ok() => Ok(()),
+            _ => ErrorInternal::new_error("The compression thread stopped"),
+        }
+    }
+
+    pub(crate) fn set_strategy(&mut self, strategy: Box<dyn BlockStrategy>) -> Result<()> {
+        self.send(Job::Strategy(strategy))
+    }
+
+    /// Hand the first `len` bytes of `block` to the background thread and replace it with a buffer of the same length.
+    ///
+    /// [`Self::finish()`] must be called before submitting the next block.
+    pub(crate) fn submit(&mut self, block: &mut Vec<u8>, len: usize) -> Result<()> {
+        if self.in_flight {
+            return ErrorInternal::new_error("A block is already being compressed");
+        }
+        let spare = self
+            .spare_block
+            .take()
+            .unwrap_or_else(|| vec![0u8; block.len()]);
+        let job = Job::Block {
+            block: mem::replace(block, spare),
+            len,
+            out: mem::take(&mut self.spare_out),
+        };
+        self.send(job)?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    /// Wait for the block being compressed, if any, and write it.
+    pub(crate) fn finish<W: Write>(&mut self, writer: &mut W) -> Result<Option<Lz4BlockHeader>> {
+        if !self.in_flight {
+            return Ok(None);
+        }
+        self.in_flight = false;
+        let encoded = match self.results.recv() {
+            Ok(result) => result?,
+            Err(_) => return ErrorInternal::new_error("The compression thread stopped"),
+        };
+        writer.write_all(&encoded.out)?;
+        self.spare_block = Some(encoded.block);
+        self.spare_out = encoded.out;
+        Ok(Some(encoded.header))
+    }
+
+    /// Stop the background thread and get the [`BlockEncoder`] back.
+    pub(crate) fn stop(mut self) -> Result<BlockEncoder<C>> {
+        self.jobs = None;
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(encoder)) => Ok(encoder),
+            _ => ErrorInternal::new_error("The compression thread stopped"),
+        }
+    }
+}
+
+impl<C: Compression> Drop for BlockPipeline<C> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
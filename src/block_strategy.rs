@@ -0,0 +1,175 @@
+use crate::lz4_block_header::CompressionMethod;
+
+use std::fmt;
+
+const SAMPLE_LEN: usize = 4096;
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.9;
+
+/// Used to decide how each block written by [`Lz4BlockOutputBase`](crate::Lz4BlockOutputBase) is stored.
+pub trait BlockStrategy: fmt::Debug + Send {
+    /// Choose the compression method of the block before compressing it.
+    ///
+    /// Returning [`CompressionMethod::Raw`] skips the compression entirely.
+    /// Returning [`None`] compresses the block and lets [`Self::accept_compressed()`] decide.
+    fn select(&self, _block: &[u8]) -> Option<CompressionMethod> {
+        None
+    }
+
+    /// Decide if the compressed data is stored instead of the raw data.
+    ///
+    /// By default, the compressed data is stored only if it is smaller.
+    fn accept_compressed(&self, decompressed_len: usize, compressed_len: usize) -> bool {
+        compressed_len < decompressed_len
+    }
+}
+
+/// The default [`BlockStrategy`]: a block is compressed with LZ4 only if it gets smaller.
+///
+/// This is the behavior of the Java implementation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmallestBlock;
+
+impl BlockStrategy for SmallestBlock {}
+
+/// A [`BlockStrategy`] storing a block with LZ4 only if it saves at least the given percentage of its size.
+///
+/// It avoids paying the decompression cost for small gains.
+#[derive(Debug, Clone, Copy)]
+pub struct MinimumSavings {
+    percent: u8,
+}
+
+impl MinimumSavings {
+    /// Create a new [`MinimumSavings`].
+    ///
+    /// The `percent` is capped to 100.
+    pub fn new(percent: u8) -> Self {
+        Self {
+            percent: percent.min(100),
+        }
+    }
+}
+
+impl BlockStrategy for MinimumSavings {
+    fn accept_compressed(&self, decompressed_len: usize, compressed_len: usize) -> bool {
+        compressed_len < decompressed_len
+            && compressed_len * 100 <= decompressed_len * (100 - self.percent as usize)
+    }
+}
+
+/// A [`BlockStrategy`] storing raw the blocks which look incompressible, without trying to compress them.
+///
+/// The entropy of the bytes is estimated on a sample of the block.
+/// It is close to 8 bits per byte for media or encrypted data, which LZ4 can't compress.
+/// The other blocks are handled by the wrapped [`BlockStrategy`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipIncompressible<S: BlockStrategy = SmallestBlock> {
+    strategy: S,
+}
+
+impl<S: BlockStrategy> SkipIncompressible<S> {
+    /// Create a new [`SkipIncompressible`] wrapping `strategy`.
+    pub fn new(strategy: S) -> Self {
+        Self { strategy }
+    }
+}
+
+impl<S: BlockStrategy> BlockStrategy for SkipIncompressible<S> {
+    fn select(&self, block: &[u8]) -> Option<CompressionMethod> {
+        if sample_entropy(block) > INCOMPRESSIBLE_ENTROPY {
+            Some(CompressionMethod::Raw)
+        } else {
+            self.strategy.select(block)
+        }
+    }
+
+    fn accept_compressed(&self, decompressed_len: usize, compressed_len: usize) -> bool {
+        self.strategy
+            .accept_compressed(decompressed_len, compressed_len)
+    }
+}
+
+/// Shannon entropy in bits per byte of at most [`SAMPLE_LEN`] bytes evenly picked from `block`.
+fn sample_entropy(block: &[u8]) -> f64 {
+    let step = (block.len() / SAMPLE_LEN).max(1);
+    let mut counts = [0usize; 256];
+    let mut total = 0usize;
+    for b in block.iter().step_by(step).take(SAMPLE_LEN) {
+        counts[*b as usize] += 1;
+        total += 1;
+    }
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test_block_strategy {
+    use super::{
+        sample_entropy, BlockStrategy, CompressionMethod, MinimumSavings, SkipIncompressible,
+        SmallestBlock,
+    };
+    use crate::lz4_block_header::data::incompressible;
+
+    #[test]
+    fn smallest_block_select() {
+        assert_eq!(SmallestBlock.select(b"..."), None);
+    }
+
+    #[test]
+    fn smallest_block_accept_compressed() {
+        assert!(SmallestBlock.accept_compressed(4, 3));
+        assert!(!SmallestBlock.accept_compressed(3, 3));
+        assert!(!SmallestBlock.accept_compressed(3, 4));
+    }
+
+    #[test]
+    fn minimum_savings_accept_compressed() {
+        let strategy = MinimumSavings::new(10);
+        assert!(strategy.accept_compressed(100, 90));
+        assert!(!strategy.accept_compressed(100, 91));
+    }
+
+    #[test]
+    fn minimum_savings_zero() {
+        let strategy = MinimumSavings::new(0);
+        assert!(strategy.accept_compressed(100, 99));
+        assert!(!strategy.accept_compressed(100, 100));
+    }
+
+    #[test]
+    fn minimum_savings_capped() {
+        let strategy = MinimumSavings::new(200);
+        assert!(strategy.accept_compressed(100, 0));
+        assert!(!strategy.accept_compressed(100, 1));
+    }
+
+    #[test]
+    fn sample_entropy_bounds() {
+        assert_eq!(sample_entropy(&[]), 0.0);
+        assert_eq!(sample_entropy(&[b'.'; 1 << 16]), 0.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(sample_entropy(&all_bytes), 8.0);
+    }
+
+    #[test]
+    fn skip_incompressible_random() {
+        let strategy = SkipIncompressible::<SmallestBlock>::default();
+        assert_eq!(
+            strategy.select(&incompressible(1 << 16)),
+            Some(CompressionMethod::Raw)
+        );
+    }
+
+    #[test]
+    fn skip_incompressible_compressible() {
+        let strategy = SkipIncompressible::new(MinimumSavings::new(10));
+        assert_eq!(strategy.select(&[b'.'; 1 << 16]), None);
+        assert!(!strategy.accept_compressed(100, 91));
+    }
+}
@@ -3,6 +3,8 @@ use lz4_flex::block::{
     CompressError as Lz4FlexCompressError, DecompressError as Lz4FlexDecompressError,
 };
 
+use crate::lz4_block_header::BlockInfo;
+
 use std::error::Error as StdError;
 use std::fmt;
 pub(crate) use std::io::{Error as IoError, ErrorKind as IoErrorKind};
@@ -242,6 +244,38 @@ impl fmt::Display for ErrorChecksum {
 }
 impl std::error::Error for ErrorChecksum {}
 
+// ErrorStreamHash
+
+#[derive(Debug)]
+pub(crate) struct ErrorStreamHash {
+    trailer_value: u64,
+    computed_value: u64,
+}
+impl ErrorStreamHash {
+    pub(crate) fn new(trailer_value: u64, computed_value: u64) -> Self {
+        Self {
+            trailer_value,
+            computed_value,
+        }
+    }
+    pub(crate) fn new_error<R, E: From<Self>>(
+        trailer_value: u64,
+        computed_value: u64,
+    ) -> StdResult<R, E> {
+        Err(Self::new(trailer_value, computed_value).into())
+    }
+}
+impl fmt::Display for ErrorStreamHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "wrong stream hash: trailer value={:016X} computed value={:016X}",
+            self.trailer_value, self.computed_value
+        )
+    }
+}
+impl std::error::Error for ErrorStreamHash {}
+
 // ErrorLz4WrongDecompressedSize
 
 #[derive(Debug)]
@@ -274,6 +308,38 @@ impl fmt::Display for ErrorLz4WrongDecompressedSize {
 }
 impl std::error::Error for ErrorLz4WrongDecompressedSize {}
 
+// ErrorBlock
+
+#[derive(Debug)]
+pub(crate) struct ErrorBlock {
+    index: u64,
+    offset: u64,
+    source: IoError,
+}
+impl ErrorBlock {
+    pub(crate) fn new(index: u64, offset: u64, source: IoError) -> Self {
+        Self {
+            index,
+            offset,
+            source,
+        }
+    }
+}
+impl fmt::Display for ErrorBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block {} at offset {}: {}",
+            self.index, self.offset, self.source
+        )
+    }
+}
+impl std::error::Error for ErrorBlock {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.source)
+    }
+}
+
 // Lz4Flex
 
 #[derive(Debug)]
@@ -340,8 +406,10 @@ pub(crate) enum Error {
     IncoherentSize(ErrorIncoherentSize),
     NoCompressionDifferentSize(ErrorNoCompressionDifferentSize),
     Checksum(ErrorChecksum),
+    StreamHash(ErrorStreamHash),
     Lz4WrongDecompressedSize(ErrorLz4WrongDecompressedSize),
     Lz4(Lz4Error),
+    Block(Box<ErrorBlock>),
     Io(IoError),
 }
 impl fmt::Display for Error {
@@ -355,8 +423,10 @@ impl fmt::Display for Error {
             Self::IncoherentSize(e) => e.fmt(f),
             Self::NoCompressionDifferentSize(e) => e.fmt(f),
             Self::Checksum(e) => e.fmt(f),
+            Self::StreamHash(e) => e.fmt(f),
             Self::Lz4WrongDecompressedSize(e) => e.fmt(f),
             Self::Lz4(e) => e.fmt(f),
+            Self::Block(e) => e.fmt(f),
             Self::Io(e) => e.fmt(f),
         }
     }
@@ -406,6 +476,11 @@ impl From<ErrorChecksum> for Error {
         Self::Checksum(error)
     }
 }
+impl From<ErrorStreamHash> for Error {
+    fn from(error: ErrorStreamHash) -> Self {
+        Self::StreamHash(error)
+    }
+}
 impl From<ErrorLz4WrongDecompressedSize> for Error {
     fn from(error: ErrorLz4WrongDecompressedSize) -> Self {
         Self::Lz4WrongDecompressedSize(error)
@@ -416,6 +491,11 @@ impl From<Lz4Error> for Error {
         Self::Lz4(error)
     }
 }
+impl From<ErrorBlock> for Error {
+    fn from(error: ErrorBlock) -> Self {
+        Self::Block(Box::new(error))
+    }
+}
 impl From<IoError> for Error {
     fn from(error: IoError) -> Self {
         Self::Io(error)
@@ -424,7 +504,7 @@ impl From<IoError> for Error {
 impl From<Error> for IoError {
     fn from(error: Error) -> Self {
         match error {
-            Error::Internal(err) => Self::new(IoErrorKind::Other, err),
+            Error::Internal(err) => Self::other(err),
             Error::MagicNumber(err) => Self::new(IoErrorKind::InvalidData, err),
             Error::CompressionMethod(err) => Self::new(IoErrorKind::InvalidData, err),
             Error::DecompressedSizeTooBig(err) => Self::new(IoErrorKind::InvalidData, err),
@@ -432,8 +512,10 @@ impl From<Error> for IoError {
             Error::IncoherentSize(err) => Self::new(IoErrorKind::InvalidData, err),
             Error::NoCompressionDifferentSize(err) => Self::new(IoErrorKind::InvalidData, err),
             Error::Checksum(err) => Self::new(IoErrorKind::InvalidData, err),
+            Error::StreamHash(err) => Self::new(IoErrorKind::InvalidData, err),
             Error::Lz4WrongDecompressedSize(err) => Self::new(IoErrorKind::InvalidData, err),
-            Error::Lz4(err) => Self::new(IoErrorKind::Other, err),
+            Error::Lz4(err) => Self::other(err),
+            Error::Block(err) => Self::new(err.source.kind(), err),
             Error::Io(err) => err,
         }
     }
@@ -462,6 +544,28 @@ impl fmt::Debug for Checksum {
     }
 }
 
+// Observer
+
+pub(crate) struct Observer<T = BlockInfo> {
+    f: Box<dyn FnMut(&T) + Send>,
+}
+
+impl<T> Observer<T> {
+    pub(crate) fn new<F: FnMut(&T) + Send + 'static>(f: F) -> Self {
+        Self { f: Box::new(f) }
+    }
+
+    pub(crate) fn run(&mut self, value: &T) {
+        (self.f)(value)
+    }
+}
+
+impl<T> fmt::Debug for Observer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
 // ErrorWrongBlockSize
 
 #[derive(Debug)]
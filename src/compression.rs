@@ -28,6 +28,31 @@ pub trait Compression {
 
     /// Find the maximum size of the output buffer when compressing.
     fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize;
+
+    /// Get the name of the LZ4 library, to report it.
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Get the version of the LZ4 library, if it can be found at runtime.
+    fn version(&self) -> Option<String> {
+        None
+    }
+
+    /// Tell if [`Self::compress()`] can use the high compression (HC) mode.
+    fn supports_hc(&self) -> bool {
+        false
+    }
+
+    /// Tell if [`Self::compress()`] and [`Self::decompress()`] can use a dictionary.
+    fn supports_dict(&self) -> bool {
+        false
+    }
+
+    /// Tell if [`Self::compress()`] can trade the compression ratio for speed with an acceleration factor.
+    fn supports_acceleration(&self) -> bool {
+        false
+    }
 }
 
 // Context
@@ -59,6 +84,20 @@ impl Default for Context {
     }
 }
 
+#[cfg(feature = "lz4_flex")]
+impl Context {
+    /// Get the [`Context`] producing the same compressed bytes for the same input, whatever the other feature flags.
+    ///
+    /// It always uses lz4_flex, which has no acceleration setting and does not depend on a system library.
+    /// Use [`Lz4BlockOutput::deterministic()`](crate::Lz4BlockOutput::deterministic) to also fix the block strategy.
+    ///
+    /// **Beware**: the compressed bytes are only checked against lz4_flex `0.11.2`, while this crate accepts any `0.11` release.
+    /// Pin it with `lz4_flex = "=0.11.2"` in the manifest of the application to keep the output stable.
+    pub fn deterministic() -> Self {
+        Self::Lz4Flex
+    }
+}
+
 impl Compression for Context {
     fn compress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
         match self {
@@ -84,6 +123,76 @@ impl Compression for Context {
             Self::Lz4Sys => lz4_sys::get_maximum_compressed_buffer_len(decompressed_len),
         }
     }
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "lz4_flex")]
+            Self::Lz4Flex => "lz4_flex",
+            #[cfg(feature = "lz4-sys")]
+            Self::Lz4Sys => "lz4-sys",
+        }
+    }
+    fn version(&self) -> Option<String> {
+        match self {
+            // lz4_flex does not give its version
+            #[cfg(feature = "lz4_flex")]
+            Self::Lz4Flex => None,
+            #[cfg(feature = "lz4-sys")]
+            Self::Lz4Sys => Some(lz4_sys::version()),
+        }
+    }
+}
+
+// ContextPair
+
+/// Use different LZ4 libraries to compress and to decompress.
+///
+/// For example, compress with `lz4-sys` for speed and decompress the untrusted inputs with `lz4_flex`.
+/// It can be given to any struct taking a [`Compression`] implementation, like [`Lz4BlockOutputBase`](crate::Lz4BlockOutputBase)
+/// or [`Lz4BlockInputBase`](crate::Lz4BlockInputBase).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ContextPair {
+    /// The library used to compress
+    pub compress: Context,
+    /// The library used to decompress
+    pub decompress: Context,
+}
+
+impl ContextPair {
+    /// Create a new [`ContextPair`].
+    pub fn new(compress: Context, decompress: Context) -> Self {
+        Self {
+            compress,
+            decompress,
+        }
+    }
+}
+
+impl Compression for ContextPair {
+    fn compress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+        self.compress.compress(input, output)
+    }
+    fn decompress(&self, input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+        self.decompress.decompress(input, output)
+    }
+    /// Get the bigger bound of both libraries.
+    ///
+    /// The bound sizes the compression buffer, and also limits the compressed length of the blocks read.
+    fn get_maximum_compressed_buffer_len(&self, decompressed_len: usize) -> usize {
+        self.compress
+            .get_maximum_compressed_buffer_len(decompressed_len)
+            .max(
+                self.decompress
+                    .get_maximum_compressed_buffer_len(decompressed_len),
+            )
+    }
+    /// Get the name of the library used to compress.
+    fn name(&self) -> &'static str {
+        self.compress.name()
+    }
+    /// Get the version of the library used to compress.
+    fn version(&self) -> Option<String> {
+        self.compress.version()
+    }
 }
 
 #[cfg(feature = "lz4_flex")]
@@ -106,7 +215,9 @@ mod lz4_flex {
 #[cfg(feature = "lz4-sys")]
 mod lz4_sys {
     use libc::{c_char, c_int};
-    use lz4_sys::{LZ4_compressBound, LZ4_compress_default, LZ4_decompress_safe};
+    use lz4_sys::{
+        LZ4_compressBound, LZ4_compress_default, LZ4_decompress_safe, LZ4_versionNumber,
+    };
 
     use crate::common::Lz4Error;
 
@@ -119,7 +230,8 @@ mod lz4_sys {
                 output.len() as c_int,
             )
         };
-        if written_bytes < 0 {
+        // 0 means the output buffer is too small
+        if written_bytes <= 0 {
             Err(Lz4Error::Lz4SysCompressError)
         } else {
             Ok(written_bytes as usize)
@@ -143,4 +255,99 @@ mod lz4_sys {
     pub(crate) fn get_maximum_compressed_buffer_len(decompressed_len: usize) -> usize {
         unsafe { LZ4_compressBound(decompressed_len as c_int) as usize }
     }
+    pub(crate) fn version() -> String {
+        // the number is major * 10000 + minor * 100 + release
+        let number = unsafe { LZ4_versionNumber() };
+        format!("{}.{}.{}", number / 10000, number / 100 % 100, number % 100)
+    }
+}
+
+#[cfg(test)]
+mod test_compression {
+    use super::{Compression, Context, ContextPair};
+    use crate::lz4_block_header::data::mixed;
+    use crate::lz4_block_input::Lz4BlockInputBase;
+    use crate::lz4_block_output::Lz4BlockOutputBase;
+
+    use std::io::{Read, Write};
+
+    fn round_trip(pair: ContextPair) {
+        let data = mixed();
+        let mut compressed = Vec::<u8>::new();
+        Lz4BlockOutputBase::with_context(&mut compressed, pair, 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        let mut out = Vec::<u8>::new();
+        Read::read_to_end(
+            &mut Lz4BlockInputBase::with_context(&compressed[..], pair),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn context_capabilities() {
+        let context = Context::default();
+        assert!(["lz4_flex", "lz4-sys"].contains(&context.name()));
+        assert!(!context.supports_hc());
+        assert!(!context.supports_dict());
+        assert!(!context.supports_acceleration());
+        assert_eq!(ContextPair::default().name(), context.name());
+    }
+
+    #[cfg(feature = "lz4-sys")]
+    #[test]
+    fn lz4_sys_version() {
+        let version = Context::Lz4Sys.version().unwrap();
+        assert_eq!(version.split('.').count(), 3);
+        assert!(version.starts_with("1."));
+    }
+
+    #[cfg(feature = "lz4_flex")]
+    #[test]
+    fn context_deterministic() {
+        let context = Context::deterministic();
+        assert_eq!(context.name(), "lz4_flex");
+        let data = b"lz4-java-wrc ".repeat(10);
+        let mut out = vec![0u8; context.get_maximum_compressed_buffer_len(data.len())];
+        let len = context.compress(&data[..128], &mut out).unwrap();
+        // the output of lz4_flex 0.11.2
+        assert_eq!(
+            out[..len],
+            [
+                223, 108, 122, 52, 45, 106, 97, 118, 97, 45, 119, 114, 99, 32, 13, 0, 90, 96, 97,
+                118, 97, 45, 119, 114
+            ]
+        );
+    }
+
+    #[test]
+    fn context_pair_default() {
+        round_trip(ContextPair::default());
+    }
+
+    #[cfg(all(feature = "lz4_flex", feature = "lz4-sys"))]
+    #[test]
+    fn context_pair_mixed() {
+        round_trip(ContextPair::new(Context::Lz4Sys, Context::Lz4Flex));
+        round_trip(ContextPair::new(Context::Lz4Flex, Context::Lz4Sys));
+    }
+
+    #[cfg(all(feature = "lz4_flex", feature = "lz4-sys"))]
+    #[test]
+    fn context_pair_maximum_compressed_buffer_len() {
+        for len in [0, 64, 1 << 16, 1 << 25] {
+            let expected = Context::Lz4Flex
+                .get_maximum_compressed_buffer_len(len)
+                .max(Context::Lz4Sys.get_maximum_compressed_buffer_len(len));
+            for pair in [
+                ContextPair::new(Context::Lz4Sys, Context::Lz4Flex),
+                ContextPair::new(Context::Lz4Flex, Context::Lz4Sys),
+            ] {
+                assert_eq!(pair.get_maximum_compressed_buffer_len(len), expected);
+            }
+        }
+    }
 }
@@ -41,16 +41,78 @@
 //!
 //! - `use_lz4_flex`: use `lz4_flex` as lz4 compression library (enabled by default)
 //! - `use_lz4-sys`: use `lz4-sys` as lz4 compression library (disabled by default)
+//! - `use_xxhash-rust`: use the faster `xxhash-rust` to compute the default checksum instead of `twox-hash` (disabled by default)
+//! - `use_arbitrary`: implement `arbitrary::Arbitrary` for [`BlockInfo`] and [`CompressionMethod`], to fuzz the readers with [`BlockInfo::to_header_bytes()`] (disabled by default)
+//! - `use_bytes`: add `Lz4BlockBytesEncoder` and `decode_blocks()`, working on `bytes::Bytes` and `bytes::BytesMut` (disabled by default)
+//! - `use_http-body`: add the `http_body::Body` wrappers `Lz4BlockEncodeBody` and `Lz4BlockDecodeBody` (disabled by default)
+//! - `use_tower`: add the `tower` layer `Lz4BlockLayer`, decompressing the requests and compressing the responses (disabled by default)
 //!
 //! When compiling with one of the lz4 compression library, it is used by default.
-//! When compiling with both of them, one can choose with the [`Context`] enum.
+//! When compiling with both of them, one can choose with the [`Context`] enum,
+//! or use one for the compression and the other for the decompression with [`ContextPair`].
 
+mod block_decoder;
+mod block_encoder;
+mod block_strategy;
 mod common;
 mod compression;
+#[cfg(feature = "http-body")]
+mod lz4_block_body;
+mod lz4_block_buf_input;
+#[cfg(feature = "bytes")]
+mod lz4_block_bytes;
+mod lz4_block_compressor;
+mod lz4_block_encoder_pool;
+mod lz4_block_file;
 mod lz4_block_header;
 mod lz4_block_input;
+#[cfg(feature = "tower-service")]
+mod lz4_block_layer;
+mod lz4_block_member;
+mod lz4_block_message;
 mod lz4_block_output;
+mod lz4_block_output_fixed;
+mod lz4_block_push_decoder;
+mod stats;
 
-pub use compression::{Compression, Context};
-pub use lz4_block_input::{Lz4BlockInput, Lz4BlockInputBase};
-pub use lz4_block_output::{Lz4BlockOutput, Lz4BlockOutputBase};
+pub use block_strategy::{BlockStrategy, MinimumSavings, SkipIncompressible, SmallestBlock};
+pub use compression::{Compression, Context, ContextPair};
+#[cfg(feature = "http-body")]
+pub use lz4_block_body::{
+    BoxError, Lz4BlockDecodeBody, Lz4BlockDecodeBodyBase, Lz4BlockEncodeBody,
+    Lz4BlockEncodeBodyBase,
+};
+pub use lz4_block_buf_input::{Lz4BlockBufInput, Lz4BlockBufInputBase};
+#[cfg(feature = "bytes")]
+pub use lz4_block_bytes::{
+    decode_blocks, Lz4BlockBytesBlocks, Lz4BlockBytesBlocksBase, Lz4BlockBytesEncoder,
+    Lz4BlockBytesEncoderBase,
+};
+pub use lz4_block_compressor::{
+    Lz4BlockCompressor, Lz4BlockCompressorBase, Lz4BlockDecompressor, Lz4BlockDecompressorBase,
+};
+pub use lz4_block_encoder_pool::{
+    Lz4BlockEncoderHandle, Lz4BlockEncoderHandleBase, Lz4BlockEncoderPool, Lz4BlockEncoderPoolBase,
+};
+pub use lz4_block_file::{Lz4BlockFile, Lz4BlockFileOptions};
+pub use lz4_block_header::{java_block_checksum, BlockInfo, CompressionMethod};
+pub use lz4_block_input::{
+    EmptyBlockPolicy, Lz4BlockInput, Lz4BlockInputBase, Lz4BlockInputBlocks, Lz4BlockInputPrefetch,
+};
+#[cfg(feature = "tower-service")]
+pub use lz4_block_layer::{
+    Lz4BlockLayer, Lz4BlockMaybeBody, Lz4BlockResponseFuture, Lz4BlockService, LZ4_BLOCK_ENCODING,
+};
+pub use lz4_block_member::{
+    Member, MemberReader, MemberReaderBase, MemberWriter, MemberWriterBase,
+};
+pub use lz4_block_message::{MessageCodec, MessageCodecBase};
+pub use lz4_block_output::{max_compressed_len, Lz4BlockOutput, Lz4BlockOutputBase};
+pub use lz4_block_output_fixed::{
+    fixed_compressed_buffer_len, Lz4BlockOutputFixed, Lz4BlockOutputFixedBase,
+};
+pub use lz4_block_push_decoder::{Event, Lz4BlockPushDecoder, Lz4BlockPushDecoderBase};
+pub use stats::{
+    stat, stat_seek, stat_seek_with_trailer, stat_with_trailer, verify, verify_with_trailer,
+    StreamStats,
+};
@@ -0,0 +1,488 @@
+use crate::block_decoder::BlockDecoder;
+use crate::block_encoder::BlockEncoder;
+use crate::common::{Checksum, IoError, IoErrorKind};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+use crate::lz4_block_output::Lz4BlockOutput;
+
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame};
+
+use std::cmp::min;
+use std::convert::TryInto;
+use std::error::Error as StdError;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+
+/// The error type of the bodies from this module.
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Wrapper around a [`Body`] to compress its data.
+///
+/// The data frames of the wrapped [`Body`] are gathered into blocks, each one sent as a data frame once compressed.
+/// The stream ends with an empty block, like the Java implementation, so `LZ4BlockInputStream` can read it.
+/// The trailers are sent after the last block.
+pub type Lz4BlockEncodeBody<B> = Lz4BlockEncodeBodyBase<B, Context>;
+
+impl<B: Body + Unpin> Lz4BlockEncodeBody<B> {
+    /// Create a new [`Lz4BlockEncodeBody`] with the default block size.
+    pub fn new(inner: B) -> Self {
+        Self::with_block_size(inner, Lz4BlockOutput::<Vec<u8>>::default_block_size()).unwrap()
+    }
+
+    /// Create a new [`Lz4BlockEncodeBody`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn with_block_size(inner: B, block_size: usize) -> std::io::Result<Self> {
+        Self::with_context(inner, Context::default(), block_size)
+    }
+}
+
+/// Wrapper around a [`Body`] to compress its data.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockEncodeBody`].
+#[derive(Debug)]
+pub struct Lz4BlockEncodeBodyBase<B, C: Compression> {
+    inner: B,
+    encoder: BlockEncoder<C>,
+    compression_level: CompressionLevel,
+    block: Vec<u8>,
+    block_size: usize,
+    inner_done: bool,
+    end_written: bool,
+    trailers: Option<http::HeaderMap>,
+}
+
+impl<B: Body + Unpin, C: Compression> Lz4BlockEncodeBodyBase<B, C> {
+    /// Create a new [`Lz4BlockEncodeBodyBase`] with the default checksum implementation.
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range.
+    pub fn with_context(inner: B, c: C, block_size: usize) -> std::io::Result<Self> {
+        let compression_level = CompressionLevel::from_block_size(block_size)?;
+        Ok(Self {
+            inner,
+            encoder: BlockEncoder::new(
+                c,
+                compression_level,
+                Checksum::new(Lz4BlockHeader::default_checksum),
+            ),
+            compression_level,
+            block: Vec::with_capacity(block_size),
+            block_size,
+            inner_done: false,
+            end_written: false,
+            trailers: None,
+        })
+    }
+
+    /// Gather `data` into blocks, compressing each full block at the end of `out`.
+    fn encode(&mut self, mut data: impl Buf, out: &mut Vec<u8>) -> Result<(), BoxError> {
+        while data.has_remaining() {
+            let size_to_copy = min(data.chunk().len(), self.block_size - self.block.len());
+            self.block.extend_from_slice(&data.chunk()[..size_to_copy]);
+            data.advance(size_to_copy);
+            if self.block.len() == self.block_size {
+                self.encode_block(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_block(&mut self, out: &mut Vec<u8>) -> Result<(), BoxError> {
+        if !self.block.is_empty() {
+            self.encoder
+                .encode_into(&self.block, out)
+                .map_err(IoError::from)?;
+            self.block.clear();
+        }
+        Ok(())
+    }
+
+    fn encode_end(&mut self) -> Result<Bytes, BoxError> {
+        let mut out = Vec::new();
+        self.encode_block(&mut out)?;
+        Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        }
+        .write(&mut out)
+        .map_err(IoError::from)?;
+        Ok(out.into())
+    }
+}
+
+impl<B, C> Body for Lz4BlockEncodeBodyBase<B, C>
+where
+    B: Body + Unpin,
+    C: Compression + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            if this.inner_done {
+                if !this.end_written {
+                    this.end_written = true;
+                    return Poll::Ready(Some(this.encode_end().map(Frame::data)));
+                }
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            }
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        let mut out = Vec::new();
+                        this.encode(data, &mut out)?;
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(out.into()))));
+                        }
+                    }
+                    Err(frame) => {
+                        this.trailers = frame.into_trailers().ok();
+                        this.inner_done = true;
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                None => this.inner_done = true,
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.end_written && self.trailers.is_none()
+    }
+}
+
+/// Wrapper around a [`Body`] to decompress its data.
+///
+/// Each data frame holds one decompressed block, so a big frame of the wrapped [`Body`] is decompressed
+/// one block per poll. It stops at the first empty block, like the Java implementation.
+/// The trailers of the wrapped [`Body`] are sent at the end.
+pub type Lz4BlockDecodeBody<B> = Lz4BlockDecodeBodyBase<B, Context>;
+
+impl<B: Body + Unpin> Lz4BlockDecodeBody<B> {
+    /// Create a new [`Lz4BlockDecodeBody`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(inner: B) -> Self {
+        Self::with_context(inner, Context::default())
+    }
+}
+
+/// Wrapper around a [`Body`] to decompress its data.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockDecodeBody`].
+#[derive(Debug)]
+pub struct Lz4BlockDecodeBodyBase<B, C: Compression> {
+    inner: B,
+    decoder: BlockDecoder<C>,
+    compressed: Vec<u8>,
+    consumed: usize,
+    inner_done: bool,
+    finished: bool,
+    trailers: Option<http::HeaderMap>,
+}
+
+impl<B: Body + Unpin, C: Compression> Lz4BlockDecodeBodyBase<B, C> {
+    /// Create a new [`Lz4BlockDecodeBodyBase`] with the default checksum implementation.
+    pub fn with_context(inner: B, c: C) -> Self {
+        Self {
+            inner,
+            decoder: BlockDecoder::new(c, Checksum::new(Lz4BlockHeader::default_checksum)),
+            compressed: Vec::new(),
+            consumed: 0,
+            inner_done: false,
+            finished: false,
+            trailers: None,
+        }
+    }
+
+    /// Decompress the next block gathered so far, if it is complete.
+    fn decode_block(&mut self) -> Result<Option<Vec<u8>>, BoxError> {
+        if self.finished {
+            return Ok(None);
+        }
+        let remaining = &self.compressed[self.consumed..];
+        if remaining.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let header = Lz4BlockHeader::parse(remaining[..HEADER_LENGTH].try_into().unwrap())
+            .map_err(IoError::from)?;
+        if header.decompressed_len == 0 {
+            self.consumed += HEADER_LENGTH;
+            self.finished = true;
+            return Ok(None);
+        }
+        self.decoder
+            .check_compressed_len(&header)
+            .map_err(IoError::from)?;
+        let block_end = HEADER_LENGTH + header.compressed_len as usize;
+        if remaining.len() < block_end {
+            return Ok(None);
+        }
+        let mut out = vec![0u8; header.decompressed_len as usize];
+        self.decoder
+            .decode_into(&header, &remaining[HEADER_LENGTH..block_end], &mut out)
+            .map_err(IoError::from)?;
+        self.consumed += block_end;
+        Ok(Some(out))
+    }
+
+    /// Append `data` after the blocks not decoded yet.
+    fn gather(&mut self, mut data: impl Buf) {
+        self.compressed.drain(..self.consumed);
+        self.consumed = 0;
+        while data.has_remaining() {
+            let size = data.chunk().len();
+            self.compressed.extend_from_slice(data.chunk());
+            data.advance(size);
+        }
+    }
+
+    fn is_incomplete(&self) -> bool {
+        !self.finished && self.consumed < self.compressed.len()
+    }
+}
+
+impl<B, C> Body for Lz4BlockDecodeBodyBase<B, C>
+where
+    B: Body + Unpin,
+    C: Compression + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            if let Some(block) = this.decode_block()? {
+                return Poll::Ready(Some(Ok(Frame::data(block.into()))));
+            }
+            if this.inner_done {
+                if this.is_incomplete() {
+                    this.compressed.clear();
+                    this.consumed = 0;
+                    return Poll::Ready(Some(Err(IoError::new(
+                        IoErrorKind::UnexpectedEof,
+                        "the body ends in the middle of a block",
+                    )
+                    .into())));
+                }
+                return Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t))));
+            }
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    // the data following the empty block is ignored
+                    Ok(_) if this.finished => {}
+                    Ok(data) => this.gather(data),
+                    Err(frame) => {
+                        this.trailers = frame.into_trailers().ok();
+                        this.inner_done = true;
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                None => this.inner_done = true,
+            }
+        }
+    }
+}
+
+impl<B, C: Compression> Lz4BlockDecodeBodyBase<B, C> {
+    /// Get back the wrapped [`Body`].
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B, C: Compression> Lz4BlockEncodeBodyBase<B, C> {
+    /// Get back the wrapped [`Body`].
+    ///
+    /// The data gathered in the current block is lost.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_lz4_block_body {
+    use super::{
+        BoxError, Lz4BlockDecodeBody, Lz4BlockDecodeBodyBase, Lz4BlockEncodeBody,
+        Lz4BlockEncodeBodyBase,
+    };
+    use crate::compression::ContextPair;
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+
+    use bytes::Bytes;
+    use http_body::{Body, Frame};
+
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    /// Get a [`Waker`] doing nothing, as the test bodies are always ready.
+    pub(crate) fn noop_waker() -> Waker {
+        Waker::from(Arc::new(NoopWaker))
+    }
+
+    /// A body sending the given data frames, then the trailers if any.
+    pub(crate) struct Frames(VecDeque<Frame<Bytes>>);
+
+    impl Frames {
+        pub(crate) fn new(chunks: Vec<Vec<u8>>, trailers: Option<http::HeaderMap>) -> Self {
+            let mut frames: VecDeque<_> = chunks
+                .into_iter()
+                .map(|c| Frame::data(Bytes::from(c)))
+                .collect();
+            frames.extend(trailers.map(Frame::trailers));
+            Self(frames)
+        }
+    }
+
+    impl Body for Frames {
+        type Data = Bytes;
+        type Error = BoxError;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, BoxError>>> {
+            Poll::Ready(self.0.pop_front().map(Ok))
+        }
+    }
+
+    /// Poll all the frames of `body`, returning the data and the trailers.
+    pub(crate) fn collect<B: Body<Data = Bytes, Error = BoxError> + Unpin>(
+        mut body: B,
+    ) -> Result<(Vec<u8>, Option<http::HeaderMap>), BoxError> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut data = Vec::new();
+        let mut trailers = None;
+        while let Poll::Ready(Some(frame)) = Pin::new(&mut body).poll_frame(&mut cx) {
+            match frame?.into_data() {
+                Ok(d) => data.extend_from_slice(&d),
+                Err(frame) => trailers = frame.into_trailers().ok(),
+            }
+        }
+        Ok((data, trailers))
+    }
+
+    fn trailers() -> http::HeaderMap {
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-test", http::HeaderValue::from_static("1"));
+        trailers
+    }
+
+    #[test]
+    fn encode_basic() {
+        let body = Frames::new(vec![b"..".to_vec(), b".".to_vec()], None);
+        let (data, trailers) =
+            collect(Lz4BlockEncodeBody::with_block_size(body, 128).unwrap()).unwrap();
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(data, expected);
+        assert_eq!(trailers, None);
+    }
+
+    #[test]
+    fn decode_basic() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(b"ignored");
+        let chunks = input.chunks(5).map(<[u8]>::to_vec).collect();
+        let (data, _) = collect(Lz4BlockDecodeBody::new(Frames::new(chunks, None))).unwrap();
+        assert_eq!(data, b"...");
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let body = Frames::new(vec![VALID_DATA[..VALID_DATA.len() - 1].to_vec()], None);
+        assert!(collect(Lz4BlockDecodeBody::new(body)).is_err());
+    }
+
+    #[test]
+    fn decode_compressed_len_too_big() {
+        let mut header = VALID_DATA;
+        // an LZ4 block of 3 bytes claiming 1GiB of compressed data
+        header[8] = 0x20;
+        header[9..13].copy_from_slice(&(1u32 << 30).to_le_bytes());
+        let body = Frames::new(vec![header[..HEADER_LENGTH].to_vec()], None);
+        let err = collect(Lz4BlockDecodeBody::new(body)).unwrap_err();
+        assert!(err.to_string().contains("compressed size"));
+    }
+
+    #[test]
+    fn decode_one_block_per_frame() {
+        let input = mixed();
+        let mut encoded = Vec::<u8>::new();
+        let mut writer =
+            crate::Lz4BlockOutput::with_context(&mut encoded, crate::Context::default(), 128)
+                .unwrap();
+        std::io::Write::write_all(&mut writer, &input).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+
+        // all the blocks arrive in a single frame
+        let mut body = Lz4BlockDecodeBody::new(Frames::new(vec![encoded], None));
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut frames = Vec::new();
+        while let Poll::Ready(Some(frame)) = Pin::new(&mut body).poll_frame(&mut cx) {
+            frames.push(frame.unwrap().into_data().unwrap());
+        }
+        assert_eq!(frames.len(), input.len().div_ceil(128));
+        assert!(frames.iter().all(|frame| frame.len() <= 128));
+        assert_eq!(frames.concat(), input);
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = mixed();
+        let chunks = input.chunks(300).map(<[u8]>::to_vec).collect();
+        let encoded =
+            Lz4BlockEncodeBody::with_block_size(Frames::new(chunks, Some(trailers())), 128)
+                .unwrap();
+        let (data, received) = collect(Lz4BlockDecodeBody::new(encoded)).unwrap();
+        assert_eq!(data, input);
+        assert_eq!(received, Some(trailers()));
+    }
+
+    #[test]
+    fn round_trip_context_pair() {
+        let input = mixed();
+        let chunks = input.chunks(300).map(<[u8]>::to_vec).collect();
+        let encoded = Lz4BlockEncodeBodyBase::with_context(
+            Frames::new(chunks, None),
+            ContextPair::default(),
+            128,
+        )
+        .unwrap();
+        let decoded = Lz4BlockDecodeBodyBase::with_context(encoded, ContextPair::default());
+        let (data, _) = collect(decoded).unwrap();
+        assert_eq!(data, input);
+    }
+}
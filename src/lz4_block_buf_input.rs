@@ -0,0 +1,301 @@
+use crate::common::{Observer, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{BlockInfo, Lz4BlockHeader};
+use crate::lz4_block_input::{
+    Buffered, EmptyBlockPolicy, InputCore, Lz4BlockInputBlocks, Lz4BlockInputPrefetch, NextBlock,
+};
+
+use twox_hash::XxHash64;
+
+use std::io::{BufRead, Read};
+
+/// Wrapper around a [`BufRead`] object to decompress data.
+///
+/// It works like [`Lz4BlockInput`](crate::Lz4BlockInput), but the headers and the compressed data are parsed
+/// directly from the buffer of the wrapped [`BufRead`] when it holds them entirely, instead of being copied first.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::Lz4BlockBufInput;
+/// use std::io::Read;
+///
+/// // &[u8] implements the BufRead trait
+/// const D: [u8; 24] = [
+///     76, 90, 52, 66, 108, 111, 99, 107, 16, 3, 0, 0, 0, 3, 0, 0, 0, 82, 228, 119, 6, 46, 46, 46,
+/// ];
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut output = String::new();
+///     Lz4BlockBufInput::new(&D[..]).read_to_string(&mut output)?;
+///     println!("{}", output);
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockBufInput<R> = Lz4BlockBufInputBase<R, Context>;
+
+impl<R: BufRead> Lz4BlockBufInput<R> {
+    /// Create a new [`Lz4BlockBufInput`] with the default [`Compression`] implementation.
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(r: R) -> Self {
+        Self::with_context(r, Context::default())
+    }
+}
+
+/// Wrapper around a [`BufRead`] object to decompress data.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockBufInput`].
+///
+/// The blocks are decoded like [`Lz4BlockInputBase`](crate::Lz4BlockInputBase) does, so the options are the same.
+#[derive(Debug)]
+pub struct Lz4BlockBufInputBase<R: BufRead + Sized, C: Compression> {
+    reader: Buffered<R>,
+    core: InputCore<C>,
+}
+
+impl<R: BufRead, C: Compression> Lz4BlockBufInputBase<R, C> {
+    /// Create a new [`Lz4BlockBufInputBase`] with the default checksum implementation which matches the Java's default implementation, including the missing 4 bits bug.
+    ///
+    /// See [`Self::with_checksum()`]
+    pub fn with_context(r: R, c: C) -> Self {
+        Self::with_checksum(
+            r,
+            c,
+            Lz4BlockHeader::default_checksum,
+            EmptyBlockPolicy::default(),
+        )
+    }
+
+    /// Create a new [`Lz4BlockBufInputBase`].
+    ///
+    /// The checksum must return a [`u32`].
+    /// The `empty_block_policy` tells what to do when reading an empty block.
+    pub fn with_checksum(
+        r: R,
+        c: C,
+        checksum: fn(&[u8]) -> u32,
+        empty_block_policy: EmptyBlockPolicy,
+    ) -> Self {
+        Self {
+            reader: Buffered(r),
+            core: InputCore::new(c, checksum, empty_block_policy),
+        }
+    }
+
+    /// See [`Lz4BlockInputBase::current_block()`](crate::Lz4BlockInputBase::current_block)
+    pub fn current_block(&self) -> Option<BlockInfo> {
+        self.core.current_block
+    }
+
+    /// See [`Lz4BlockInputBase::set_diagnostics()`](crate::Lz4BlockInputBase::set_diagnostics)
+    pub fn set_diagnostics(&mut self, diagnostics: bool) {
+        self.core.diagnostics = diagnostics;
+    }
+
+    /// See [`Lz4BlockInputBase::set_accept_unmasked_checksum()`](crate::Lz4BlockInputBase::set_accept_unmasked_checksum)
+    pub fn set_accept_unmasked_checksum(&mut self, accept_unmasked_checksum: bool) {
+        self.core
+            .decoder
+            .set_accept_unmasked_checksum(accept_unmasked_checksum);
+    }
+
+    /// See [`Lz4BlockInputBase::unmasked_checksum_seen()`](crate::Lz4BlockInputBase::unmasked_checksum_seen)
+    pub fn unmasked_checksum_seen(&self) -> bool {
+        self.core.unmasked_checksum_seen
+    }
+
+    /// See [`Lz4BlockInputBase::set_strict_compressed_len()`](crate::Lz4BlockInputBase::set_strict_compressed_len)
+    pub fn set_strict_compressed_len(&mut self, strict_compressed_len: bool) {
+        self.core.strict_compressed_len = strict_compressed_len;
+    }
+
+    /// See [`Lz4BlockInputBase::set_xxh64_trailer()`](crate::Lz4BlockInputBase::set_xxh64_trailer)
+    pub fn set_xxh64_trailer(&mut self, xxh64_trailer: bool) {
+        self.core.stream_hash = xxh64_trailer.then(|| XxHash64::with_seed(0));
+    }
+
+    /// See [`Lz4BlockInputBase::set_observer()`](crate::Lz4BlockInputBase::set_observer)
+    pub fn set_observer<F: FnMut(&BlockInfo) + Send + 'static>(&mut self, observer: F) {
+        self.core.observer = Some(Observer::new(observer));
+    }
+
+    /// See [`Lz4BlockInputBase::members_read()`](crate::Lz4BlockInputBase::members_read)
+    pub fn members_read(&self) -> u64 {
+        self.core.members
+    }
+
+    /// See [`Lz4BlockInputBase::set_member_observer()`](crate::Lz4BlockInputBase::set_member_observer)
+    pub fn set_member_observer<F: FnMut(&u64) + Send + 'static>(&mut self, member_observer: F) {
+        self.core.member_observer = Some(Observer::new(member_observer));
+    }
+
+    /// See [`Lz4BlockInputBase::into_prefetch()`](crate::Lz4BlockInputBase::into_prefetch)
+    pub fn into_prefetch(self) -> Lz4BlockInputPrefetch
+    where
+        R: Send + 'static,
+        C: Send + 'static,
+    {
+        Lz4BlockInputPrefetch::spawn(self.reader, self.core)
+    }
+
+    /// See [`Lz4BlockInputBase::blocks()`](crate::Lz4BlockInputBase::blocks)
+    pub fn blocks(&mut self) -> Lz4BlockInputBlocks<'_> {
+        Lz4BlockInputBlocks::new(self)
+    }
+
+    /// See [`Lz4BlockInputBase::next_block()`](crate::Lz4BlockInputBase::next_block)
+    pub fn next_block(&mut self) -> std::io::Result<Option<&[u8]>> {
+        Ok(self.core.next_block(&mut self.reader)?)
+    }
+}
+
+impl<R: BufRead, C: Compression> NextBlock for Lz4BlockBufInputBase<R, C> {
+    fn next_block(&mut self) -> Result<Option<&[u8]>> {
+        self.core.next_block(&mut self.reader)
+    }
+}
+
+impl<R: BufRead, C: Compression> Read for Lz4BlockBufInputBase<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.core.read(&mut self.reader, buf)?)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        Ok(self.core.read_to_end(&mut self.reader, buf)?)
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_buf_input {
+    use super::Lz4BlockBufInput;
+    use crate::compression::Context;
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::Lz4BlockHeader;
+    use crate::lz4_block_input::EmptyBlockPolicy;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{BufReader, Cursor, Read, Write};
+
+    #[test]
+    fn read_basic() {
+        let mut out = Vec::<u8>::new();
+        Lz4BlockBufInput::new(&VALID_DATA[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, "...".as_bytes());
+    }
+
+    #[test]
+    fn read_empty() {
+        let mut out = Vec::<u8>::new();
+        Lz4BlockBufInput::new(&VALID_EMPTY[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, []);
+    }
+
+    #[test]
+    fn read_invalid_checksum() {
+        let mut input = VALID_DATA.to_vec();
+        *input.last_mut().unwrap() = b'#';
+        assert!(Lz4BlockBufInput::new(&input[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn read_member_boundary() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&[0; 30]);
+        let read = |input: &[u8]| {
+            let mut out = Vec::<u8>::new();
+            Lz4BlockBufInput::with_checksum(
+                input,
+                Context::default(),
+                Lz4BlockHeader::default_checksum,
+                EmptyBlockPolicy::TreatAsMemberBoundary,
+            )
+            .read_to_end(&mut out)
+            .map(|_| out)
+        };
+        assert_eq!(read(&input).unwrap(), b"......");
+
+        // a corrupted second member is not mistaken for padding
+        input[VALID_DATA.len() + VALID_EMPTY.len()] ^= 1;
+        assert!(read(&input).is_err());
+    }
+
+    #[test]
+    fn read_with_input_options() {
+        let mut input = Vec::<u8>::new();
+        let mut writer = Lz4BlockOutput::with_context(&mut input, Context::default(), 128).unwrap();
+        writer.set_xxh64_trailer(true);
+        writer.write_all(&[b'.'; 1000]).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+
+        for capacity in [1, 30, 1 << 16] {
+            let mut reader = Lz4BlockBufInput::new(BufReader::with_capacity(capacity, &input[..]));
+            reader.set_xxh64_trailer(true);
+            reader.set_strict_compressed_len(true);
+            let mut out = Vec::<u8>::new();
+            reader.read_to_end(&mut out).unwrap();
+            assert_eq!(out, [b'.'; 1000]);
+            assert_eq!(reader.members_read(), 1);
+        }
+
+        *input.last_mut().unwrap() ^= 1;
+        let mut reader = Lz4BlockBufInput::new(&input[..]);
+        reader.set_xxh64_trailer(true);
+        reader.set_diagnostics(true);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(err.to_string().starts_with("block "));
+    }
+
+    #[test]
+    fn read_split_buffers() {
+        let data = mixed();
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        // small buffers split the headers and the compressed data between reads
+        for capacity in [1, 7, 30, 1 << 16] {
+            let mut out = Vec::<u8>::new();
+            Lz4BlockBufInput::new(BufReader::with_capacity(capacity, &input[..]))
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, data);
+        }
+    }
+
+    #[test]
+    fn read_prefetch_and_blocks() {
+        let data = mixed();
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let mut out = Vec::<u8>::new();
+        Lz4BlockBufInput::new(Cursor::new(input.clone()))
+            .into_prefetch()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+
+        let blocks: Vec<Vec<u8>> = Lz4BlockBufInput::new(&input[..])
+            .blocks()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(blocks.len(), data.len().div_ceil(128));
+        assert_eq!(blocks.concat(), data);
+    }
+}
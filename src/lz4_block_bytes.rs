@@ -0,0 +1,355 @@
+use crate::block_decoder::BlockDecoder;
+use crate::block_encoder::BlockEncoder;
+use crate::common::{Checksum, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use std::cmp::min;
+
+/// Compress data directly into a [`BytesMut`].
+///
+/// Each call appends complete blocks, so the output can be split and sent as soon as it is written.
+///
+/// # Example
+///
+/// ```rust
+/// use bytes::BytesMut;
+/// use lz4_java_wrc::{decode_blocks, Lz4BlockBytesEncoder};
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut encoder = Lz4BlockBytesEncoder::new(1 << 16)?;
+///     let mut out = BytesMut::new();
+///     encoder.encode_into("...".as_bytes(), &mut out)?;
+///     encoder.encode_end(&mut out)?;
+///
+///     for block in decode_blocks(out.freeze()) {
+///         println!("{:?}", block?);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockBytesEncoder = Lz4BlockBytesEncoderBase<Context>;
+
+impl Lz4BlockBytesEncoder {
+    /// Create a new [`Lz4BlockBytesEncoder`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(block_size: usize) -> std::io::Result<Self> {
+        Self::with_context(Context::default(), block_size)
+    }
+}
+
+/// Compress data directly into a [`BytesMut`].
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockBytesEncoder`].
+#[derive(Debug)]
+pub struct Lz4BlockBytesEncoderBase<C: Compression> {
+    encoder: BlockEncoder<C>,
+    compression_level: CompressionLevel,
+    block_size: usize,
+    max_block_len: usize,
+}
+
+impl<C: Compression> Lz4BlockBytesEncoderBase<C> {
+    /// Create a new [`Lz4BlockBytesEncoderBase`] with the default checksum implementation.
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    pub fn with_context(c: C, block_size: usize) -> std::io::Result<Self> {
+        let compression_level = CompressionLevel::from_block_size(block_size)?;
+        let max_block_len = HEADER_LENGTH + c.get_maximum_compressed_buffer_len(block_size);
+        Ok(Self {
+            encoder: BlockEncoder::new(
+                c,
+                compression_level,
+                Checksum::new(Lz4BlockHeader::default_checksum),
+            ),
+            compression_level,
+            block_size,
+            max_block_len,
+        })
+    }
+
+    /// Compress all of `data` at the end of `out`, in blocks of the block size.
+    ///
+    /// The last block may be smaller. No empty block is written, see [`Self::encode_end()`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block could not be compressed.
+    pub fn encode_into<B: Buf>(&mut self, mut data: B, out: &mut BytesMut) -> std::io::Result<()> {
+        let mut block = Vec::new();
+        while data.has_remaining() {
+            if data.chunk().len() >= self.block_size || data.chunk().len() == data.remaining() {
+                // the block is contiguous in the source
+                let len = min(data.chunk().len(), self.block_size);
+                self.encode_block_into(&data.chunk()[..len], out)?;
+                data.advance(len);
+            } else {
+                block.resize(min(data.remaining(), self.block_size), 0);
+                data.copy_to_slice(&mut block);
+                self.encode_block_into(&block, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compress `block` as a single block at the end of `out`.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if `block` is larger than the block size, or if it could not be compressed.
+    pub fn encode_block_into(&mut self, block: &[u8], out: &mut BytesMut) -> std::io::Result<()> {
+        if block.len() > self.block_size {
+            return Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                "the block is larger than the block size",
+            ));
+        }
+        if block.is_empty() {
+            return Ok(());
+        }
+        out.reserve(self.max_block_len);
+        Ok(Self::encode_block_inner(self, block, out)?)
+    }
+
+    fn encode_block_inner(&mut self, block: &[u8], out: &mut BytesMut) -> Result<()> {
+        self.encoder.encode_into(block, &mut out.writer())?;
+        Ok(())
+    }
+
+    /// Write the empty block ending the stream at the end of `out`, like the Java implementation.
+    ///
+    /// # Errors
+    ///
+    /// It never fails, the result is kept for consistency with the other methods.
+    pub fn encode_end(&self, out: &mut BytesMut) -> std::io::Result<()> {
+        Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        }
+        .write(&mut out.writer())?;
+        Ok(())
+    }
+}
+
+/// Decompress the blocks held by `buf` with the default [`Context`].
+///
+/// See [`Lz4BlockBytesBlocks`]
+pub fn decode_blocks<B: Buf>(buf: B) -> Lz4BlockBytesBlocks<B> {
+    Lz4BlockBytesBlocks::with_context(buf, Context::default())
+}
+
+/// Iterator over the decompressed blocks held by a [`Buf`], given by [`decode_blocks()`].
+///
+/// The raw blocks are taken from the [`Buf`] with [`Buf::copy_to_bytes()`],
+/// which does not copy them when it is a [`Bytes`].
+/// It stops at the first empty block, like the Java implementation, or at the end of the [`Buf`].
+/// After an error, it returns nothing more.
+pub type Lz4BlockBytesBlocks<B> = Lz4BlockBytesBlocksBase<B, Context>;
+
+/// Iterator over the decompressed blocks held by a [`Buf`].
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockBytesBlocks`].
+#[derive(Debug)]
+pub struct Lz4BlockBytesBlocksBase<B: Buf, C: Compression> {
+    buf: B,
+    decoder: BlockDecoder<C>,
+    finished: bool,
+}
+
+impl<B: Buf, C: Compression> Lz4BlockBytesBlocksBase<B, C> {
+    /// Create a new [`Lz4BlockBytesBlocksBase`] with the default checksum implementation.
+    pub fn with_context(buf: B, c: C) -> Self {
+        Self {
+            buf,
+            decoder: BlockDecoder::new(c, Checksum::new(Lz4BlockHeader::default_checksum)),
+            finished: false,
+        }
+    }
+
+    /// Get back the [`Buf`], positioned after the last block returned.
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+
+    fn next_block(&mut self) -> Result<Option<Bytes>> {
+        if !self.buf.has_remaining() {
+            return Ok(None);
+        }
+        if self.buf.remaining() < HEADER_LENGTH {
+            return Err(truncated_error().into());
+        }
+        let mut header = [0u8; HEADER_LENGTH];
+        self.buf.copy_to_slice(&mut header);
+        let header = Lz4BlockHeader::parse(&header)?;
+        if header.decompressed_len == 0 {
+            return Ok(None);
+        }
+        self.decoder.check_compressed_len(&header)?;
+        let compressed_len = header.compressed_len as usize;
+        if self.buf.remaining() < compressed_len {
+            return Err(truncated_error().into());
+        }
+        match header.compression_method {
+            CompressionMethod::Raw => {
+                let block = self.buf.copy_to_bytes(compressed_len);
+                self.decoder.check(&header, &block)?;
+                Ok(Some(block))
+            }
+            CompressionMethod::Lz4 => {
+                let mut block = BytesMut::zeroed(header.decompressed_len as usize);
+                if self.buf.chunk().len() >= compressed_len {
+                    self.decoder.decode_into(
+                        &header,
+                        &self.buf.chunk()[..compressed_len],
+                        &mut block,
+                    )?;
+                    self.buf.advance(compressed_len);
+                } else {
+                    let compressed = self.buf.copy_to_bytes(compressed_len);
+                    self.decoder.decode_into(&header, &compressed, &mut block)?;
+                }
+                Ok(Some(block.freeze()))
+            }
+        }
+    }
+}
+
+impl<B: Buf, C: Compression> Iterator for Lz4BlockBytesBlocksBase<B, C> {
+    type Item = std::io::Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.next_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+fn truncated_error() -> IoError {
+    IoError::new(
+        IoErrorKind::UnexpectedEof,
+        "the buffer ends in the middle of a block",
+    )
+}
+
+#[cfg(test)]
+mod test_lz4_block_bytes {
+    use super::{
+        decode_blocks, Lz4BlockBytesBlocksBase, Lz4BlockBytesEncoder, Lz4BlockBytesEncoderBase,
+    };
+    use crate::compression::{Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use bytes::{Buf, Bytes, BytesMut};
+
+    use std::io::{ErrorKind, Write};
+
+    #[test]
+    fn encode_same_as_lz4_block_output() {
+        let data = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        // a chained buffer splits the blocks between chunks
+        let (first, second) = data.split_at(100);
+        let mut encoder = Lz4BlockBytesEncoder::new(128).unwrap();
+        let mut out = BytesMut::new();
+        encoder.encode_into(first.chain(second), &mut out).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn round_trip_context_pair() {
+        let data = mixed();
+        let mut encoder =
+            Lz4BlockBytesEncoderBase::with_context(ContextPair::default(), 128).unwrap();
+        let mut out = BytesMut::new();
+        encoder.encode_into(&data[..], &mut out).unwrap();
+
+        let blocks = Lz4BlockBytesBlocksBase::with_context(out.freeze(), ContextPair::default());
+        let decoded: Vec<Bytes> = blocks.map(Result::unwrap).collect();
+        assert_eq!(decoded.concat(), data);
+    }
+
+    #[test]
+    fn encode_block_too_large() {
+        let mut encoder = Lz4BlockBytesEncoder::new(64).unwrap();
+        let err = encoder
+            .encode_block_into(&[b'.'; 65], &mut BytesMut::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn encode_end() {
+        let encoder = Lz4BlockBytesEncoder::new(64).unwrap();
+        let mut out = BytesMut::new();
+        encoder.encode_end(&mut out).unwrap();
+        assert_eq!(out, VALID_EMPTY[..]);
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        let data = mixed();
+        let mut encoder = Lz4BlockBytesEncoder::new(128).unwrap();
+        let mut out = BytesMut::new();
+        encoder.encode_into(&data[..], &mut out).unwrap();
+        encoder.encode_end(&mut out).unwrap();
+        out.extend_from_slice(b"trailing");
+
+        let mut blocks = decode_blocks(out.freeze());
+        let decoded: Vec<Bytes> = blocks.by_ref().map(Result::unwrap).collect();
+        assert_eq!(decoded.len(), 32);
+        assert_eq!(decoded.concat(), data);
+        assert_eq!(blocks.into_inner(), &b"trailing"[..]);
+    }
+
+    #[test]
+    fn decode_raw_without_copy() {
+        let input = Bytes::from_static(&VALID_DATA);
+        let block = decode_blocks(input.clone()).next().unwrap().unwrap();
+        assert_eq!(block, &b"..."[..]);
+        assert_eq!(block.as_ptr(), input[VALID_DATA.len() - 3..].as_ptr());
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let mut blocks = decode_blocks(&VALID_DATA[..VALID_DATA.len() - 1]);
+        assert_eq!(
+            blocks.next().unwrap().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn decode_invalid_checksum() {
+        let mut input = VALID_DATA.to_vec();
+        *input.last_mut().unwrap() = b'#';
+        assert!(decode_blocks(&input[..]).next().unwrap().is_err());
+    }
+}
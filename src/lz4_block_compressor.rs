@@ -0,0 +1,451 @@
+use crate::block_decoder::BlockDecoder;
+use crate::block_encoder::BlockEncoder;
+use crate::common::{Checksum, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+use crate::lz4_block_input::ensure_vec;
+use crate::lz4_block_output::Lz4BlockOutput;
+
+use std::cmp::min;
+use std::convert::TryInto;
+
+/// Compress byte slices into blocks, without any [`Write`](std::io::Write) object.
+///
+/// The compression buffer is kept between the calls, so the same [`Lz4BlockCompressor`] should be reused.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{max_compressed_len, Context, Lz4BlockCompressor, Lz4BlockDecompressor};
+///
+/// fn main() -> std::io::Result<()> {
+///     let data = "...".repeat(1000);
+///     let mut compressor = Lz4BlockCompressor::new(1 << 16)?;
+///     let mut compressed = vec![0u8; max_compressed_len(&Context::default(), data.len(), 1 << 16)?];
+///     let (consumed, mut len) = compressor.compress(data.as_bytes(), &mut compressed)?;
+///     assert_eq!(consumed, data.len());
+///     len += compressor.compress_end(&mut compressed[len..])?;
+///
+///     let mut decompressor = Lz4BlockDecompressor::new();
+///     let mut decompressed = vec![0u8; data.len()];
+///     decompressor.decompress(&compressed[..len], &mut decompressed)?;
+///     assert!(decompressor.is_finished());
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockCompressor = Lz4BlockCompressorBase<Context>;
+
+impl Lz4BlockCompressor {
+    /// Create a new [`Lz4BlockCompressor`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(block_size: usize) -> std::io::Result<Self> {
+        Self::with_context(Context::default(), block_size)
+    }
+}
+
+/// Compress byte slices into blocks, without any [`Write`](std::io::Write) object.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockCompressor`].
+#[derive(Debug)]
+pub struct Lz4BlockCompressorBase<C: Compression> {
+    encoder: BlockEncoder<C>,
+    compression_level: CompressionLevel,
+    block_size: usize,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl<C: Compression> Lz4BlockCompressorBase<C> {
+    /// Create a new [`Lz4BlockCompressorBase`] with the default checksum implementation.
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    pub fn with_context(c: C, block_size: usize) -> std::io::Result<Self> {
+        let compression_level = CompressionLevel::from_block_size(block_size)?;
+        Ok(Self {
+            encoder: BlockEncoder::new(
+                c,
+                compression_level,
+                Checksum::new(Lz4BlockHeader::default_checksum),
+            ),
+            compression_level,
+            block_size,
+            total_in: 0,
+            total_out: 0,
+        })
+    }
+
+    /// Compress `input` into `output`, in blocks of the block size.
+    ///
+    /// It returns the number of bytes consumed from `input` and written to `output`.
+    /// It stops before the first block which could not fit in `output` once compressed, that is when `output`
+    /// has less room left than the header and the maximum compressed length of the block.
+    /// So only complete blocks are written, and no block is compressed for nothing:
+    /// the bytes not consumed must be given again to the next call, with more room in `output`.
+    /// [`max_compressed_len()`](crate::max_compressed_len) bytes are enough to compress all of `input` at once,
+    /// followed by [`Self::compress_end()`].
+    /// No empty block is written, see [`Self::compress_end()`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block could not be compressed.
+    pub fn compress(&mut self, input: &[u8], output: &mut [u8]) -> std::io::Result<(usize, usize)> {
+        Ok(Self::compress_inner(self, input, output)?)
+    }
+
+    fn compress_inner(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize)> {
+        let mut consumed = 0;
+        let mut written = 0;
+        for block in input.chunks(self.block_size) {
+            if output.len() - written
+                < HEADER_LENGTH + self.encoder.get_maximum_encoded_len(block.len())
+            {
+                break;
+            }
+            // the block is compressed into the buffer of the encoder before being copied
+            let (header, data) = self.encoder.encode(block)?;
+            let block_end = written + HEADER_LENGTH + data.len();
+            header.write(&mut &mut output[written..written + HEADER_LENGTH])?;
+            output[written + HEADER_LENGTH..block_end].copy_from_slice(data);
+            consumed += block.len();
+            written = block_end;
+        }
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+        Ok((consumed, written))
+    }
+
+    /// Write the empty block ending the stream into `output`, like the Java implementation.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error of kind [`WriteZero`](std::io::ErrorKind::WriteZero) if `output` is too small,
+    /// without writing anything.
+    pub fn compress_end(&mut self, output: &mut [u8]) -> std::io::Result<usize> {
+        Ok(Self::compress_end_inner(self, output)?)
+    }
+
+    fn compress_end_inner(&mut self, output: &mut [u8]) -> Result<usize> {
+        if output.len() < HEADER_LENGTH {
+            return Err(IoError::from(IoErrorKind::WriteZero).into());
+        }
+        let written = Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        }
+        .write(&mut &mut output[..HEADER_LENGTH])?;
+        self.total_out += written as u64;
+        Ok(written)
+    }
+
+    /// Get the number of bytes compressed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Get the number of bytes written so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl Default for Lz4BlockCompressor {
+    fn default() -> Self {
+        Self::new(Lz4BlockOutput::<Vec<u8>>::default_block_size()).unwrap()
+    }
+}
+
+/// Decompress byte slices holding blocks, without any [`Read`](std::io::Read) object.
+///
+/// The input and the output can be given in pieces of any size:
+/// a block is only consumed once the input holds it entirely,
+/// and the decompressed data not fitting in the output is kept for the next call.
+/// It stops at the first empty block, like the Java implementation.
+pub type Lz4BlockDecompressor = Lz4BlockDecompressorBase<Context>;
+
+impl Lz4BlockDecompressor {
+    /// Create a new [`Lz4BlockDecompressor`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new() -> Self {
+        Self::with_context(Context::default())
+    }
+}
+
+/// Decompress byte slices holding blocks, without any [`Read`](std::io::Read) object.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockDecompressor`].
+#[derive(Debug)]
+pub struct Lz4BlockDecompressorBase<C: Compression> {
+    decoder: BlockDecoder<C>,
+    pending: Vec<u8>,
+    pending_ptr: usize,
+    finished: bool,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl<C: Compression> Lz4BlockDecompressorBase<C> {
+    /// Create a new [`Lz4BlockDecompressorBase`] with the default checksum implementation.
+    pub fn with_context(c: C) -> Self {
+        Self {
+            decoder: BlockDecoder::new(c, Checksum::new(Lz4BlockHeader::default_checksum)),
+            pending: Vec::new(),
+            pending_ptr: 0,
+            finished: false,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Decompress the complete blocks of `input` into `output`.
+    ///
+    /// It returns the number of bytes consumed from `input` and written to `output`.
+    /// The bytes not consumed must be given again to the next call, followed by the next ones.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block is invalid.
+    pub fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> std::io::Result<(usize, usize)> {
+        Ok(Self::decompress_inner(self, input, output)?)
+    }
+
+    fn decompress_inner(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize)> {
+        let mut consumed = 0;
+        let mut written = self.copy_pending(output);
+        while self.pending_ptr == self.pending.len() && !self.finished {
+            let remaining = &input[consumed..];
+            if remaining.len() < HEADER_LENGTH {
+                break;
+            }
+            let header = Lz4BlockHeader::parse(remaining[..HEADER_LENGTH].try_into().unwrap())?;
+            if header.decompressed_len == 0 {
+                consumed += HEADER_LENGTH;
+                self.finished = true;
+                break;
+            }
+            self.decoder.check_compressed_len(&header)?;
+            let block_end = HEADER_LENGTH + header.compressed_len as usize;
+            if remaining.len() < block_end {
+                break;
+            }
+            let data = &remaining[HEADER_LENGTH..block_end];
+            let decompressed_len = header.decompressed_len as usize;
+            if output.len() - written >= decompressed_len {
+                self.decoder.decode_into(
+                    &header,
+                    data,
+                    &mut output[written..written + decompressed_len],
+                )?;
+                written += decompressed_len;
+            } else {
+                ensure_vec(
+                    &mut self.pending,
+                    header.compression_level.get_max_decompressed_buffer_len(),
+                    header.decompressed_len,
+                );
+                self.decoder.decode_into(&header, data, &mut self.pending)?;
+                self.pending_ptr = 0;
+                written += self.copy_pending(&mut output[written..]);
+            }
+            consumed += block_end;
+        }
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+        Ok((consumed, written))
+    }
+
+    fn copy_pending(&mut self, output: &mut [u8]) -> usize {
+        let size_to_copy = min(output.len(), self.pending.len() - self.pending_ptr);
+        output[..size_to_copy]
+            .copy_from_slice(&self.pending[self.pending_ptr..self.pending_ptr + size_to_copy]);
+        self.pending_ptr += size_to_copy;
+        size_to_copy
+    }
+
+    /// Tell if the empty block was read and all the decompressed data was given back.
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.pending_ptr == self.pending.len()
+    }
+
+    /// Forget the current stream to decompress a new one, keeping the buffers.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.pending_ptr = 0;
+        self.finished = false;
+        self.total_in = 0;
+        self.total_out = 0;
+    }
+
+    /// Get the number of bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Get the number of bytes decompressed so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl Default for Lz4BlockDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_compressor {
+    use super::{
+        Lz4BlockCompressor, Lz4BlockCompressorBase, Lz4BlockDecompressor, Lz4BlockDecompressorBase,
+    };
+    use crate::compression::{Compression, Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+    use crate::lz4_block_output::{max_compressed_len, Lz4BlockOutput};
+
+    use std::io::Write;
+
+    #[test]
+    fn compress_same_as_lz4_block_output() {
+        let data = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let mut compressor = Lz4BlockCompressor::with_context(Context::default(), 128).unwrap();
+        let mut out = vec![0u8; max_compressed_len(&Context::default(), data.len(), 128).unwrap()];
+        let (consumed, len) = compressor.compress(&data, &mut out).unwrap();
+        assert_eq!(consumed, data.len());
+        assert_eq!(out[..len], expected);
+        assert_eq!(compressor.total_in(), data.len() as u64);
+        assert_eq!(compressor.total_out(), len as u64);
+    }
+
+    #[test]
+    fn round_trip_context_pair() {
+        let data = mixed();
+        let pair = ContextPair::default();
+        let mut compressor = Lz4BlockCompressorBase::with_context(pair, 128).unwrap();
+        let mut compressed = vec![0u8; max_compressed_len(&pair, data.len(), 128).unwrap()];
+        let (_, mut len) = compressor.compress(&data, &mut compressed).unwrap();
+        len += compressor.compress_end(&mut compressed[len..]).unwrap();
+
+        let mut decompressor = Lz4BlockDecompressorBase::with_context(pair);
+        let mut out = vec![0u8; data.len()];
+        decompressor
+            .decompress(&compressed[..len], &mut out)
+            .unwrap();
+        assert!(decompressor.is_finished());
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn compress_output_too_small() {
+        let mut compressor = Lz4BlockCompressor::new(64).unwrap();
+        let mut out = [0u8; VALID_DATA.len() - 1];
+        assert_eq!(compressor.compress(b"...", &mut out).unwrap(), (0, 0));
+        assert_eq!(out, [0u8; VALID_DATA.len() - 1]);
+        assert!(compressor.compress_end(&mut out[..10]).is_err());
+        assert_eq!(out, [0u8; VALID_DATA.len() - 1]);
+        assert_eq!(compressor.total_out(), 0);
+
+        let mut out = [0u8; HEADER_LENGTH];
+        assert_eq!(compressor.compress_end(&mut out).unwrap(), HEADER_LENGTH);
+        assert_eq!(out, VALID_EMPTY);
+
+        // the room is checked before compressing, with the maximum compressed length of the block
+        let bound = HEADER_LENGTH + Context::default().get_maximum_compressed_buffer_len(3);
+        let mut larger = vec![0u8; bound];
+        assert_eq!(
+            compressor
+                .compress(b"...", &mut larger[..bound - 1])
+                .unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            compressor.compress(b"...", &mut larger).unwrap(),
+            (3, VALID_DATA.len())
+        );
+        assert_eq!(larger[..VALID_DATA.len()], VALID_DATA);
+    }
+
+    #[test]
+    fn compress_in_pieces() {
+        let data = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let mut compressor = Lz4BlockCompressor::with_context(Context::default(), 128).unwrap();
+        let mut out = Vec::new();
+        let mut consumed = 0;
+        while consumed < data.len() {
+            let mut buf = [0u8; 200];
+            let (c, w) = compressor.compress(&data[consumed..], &mut buf).unwrap();
+            assert!(c > 0);
+            consumed += c;
+            out.extend_from_slice(&buf[..w]);
+        }
+        assert_eq!(out, expected);
+        assert_eq!(compressor.total_in(), data.len() as u64);
+        assert_eq!(compressor.total_out(), out.len() as u64);
+    }
+
+    #[test]
+    fn decompress_in_pieces() {
+        let data = mixed();
+        let mut compressor = Lz4BlockCompressor::with_context(Context::default(), 128).unwrap();
+        let mut compressed =
+            vec![0u8; max_compressed_len(&Context::default(), data.len(), 128).unwrap()];
+        let (_, mut len) = compressor.compress(&data, &mut compressed).unwrap();
+        len += compressor.compress_end(&mut compressed[len..]).unwrap();
+        compressed.truncate(len);
+        compressed.extend_from_slice(b"trailing");
+
+        let mut decompressor = Lz4BlockDecompressor::new();
+        for (input_step, output_step) in [(1, 1), (7, 50), (200, 1000), (1 << 16, 1 << 16)] {
+            decompressor.reset();
+            let mut out = Vec::new();
+            let mut input_end = 0;
+            let mut consumed = 0;
+            while !decompressor.is_finished() {
+                input_end = (input_end + input_step).min(compressed.len());
+                let mut buf = vec![0u8; output_step];
+                let (c, w) = decompressor
+                    .decompress(&compressed[consumed..input_end], &mut buf)
+                    .unwrap();
+                consumed += c;
+                out.extend_from_slice(&buf[..w]);
+            }
+            assert_eq!(out, data);
+            assert_eq!(&compressed[consumed..], b"trailing");
+            assert_eq!(decompressor.total_in(), consumed as u64);
+            assert_eq!(decompressor.total_out(), data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn decompress_invalid() {
+        let mut input = VALID_DATA.to_vec();
+        *input.last_mut().unwrap() = b'#';
+        assert!(Lz4BlockDecompressor::new()
+            .decompress(&input, &mut [0u8; 10])
+            .is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use crate::block_encoder::BlockEncoder;
+use crate::common::{Checksum, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader};
+use crate::lz4_block_output::Lz4BlockOutput;
+
+use std::io::Write;
+use std::sync::Mutex;
+
+/// A pool of encoders sharing the same configuration, to be used by several threads.
+///
+/// Each [`Lz4BlockEncoderHandle`] keeps its compression buffer, and goes back to the pool when dropped,
+/// so the buffers are allocated only when all the handles are in use.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::Lz4BlockEncoderPool;
+///
+/// fn main() -> std::io::Result<()> {
+///     let pool = Lz4BlockEncoderPool::new(1 << 16)?;
+///     std::thread::scope(|s| {
+///         for _ in 0..4 {
+///             s.spawn(|| {
+///                 let mut output = Vec::new();
+///                 let mut encoder = pool.get();
+///                 encoder.compress_to("...".as_bytes(), &mut output)?;
+///                 encoder.write_end(&mut output)?;
+///                 println!("{:?}", output);
+///                 std::io::Result::Ok(())
+///             });
+///         }
+///     });
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockEncoderPool = Lz4BlockEncoderPoolBase<Context>;
+
+impl Lz4BlockEncoderPool {
+    /// Create a new [`Lz4BlockEncoderPool`] with the default [`Context`] and checksum.
+    ///
+    /// See [`Self::with_checksum()`]
+    pub fn new(block_size: usize) -> std::io::Result<Self> {
+        Self::with_checksum(
+            Context::default(),
+            block_size,
+            Lz4BlockHeader::default_checksum,
+        )
+    }
+}
+
+/// A pool of encoders sharing the same configuration, to be used by several threads.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockEncoderPool`].
+#[derive(Debug)]
+pub struct Lz4BlockEncoderPoolBase<C: Compression> {
+    context: C,
+    compression_level: CompressionLevel,
+    block_size: usize,
+    checksum: fn(&[u8]) -> u32,
+    idle: Mutex<Vec<BlockEncoder<C>>>,
+}
+
+impl<C: Compression + Clone> Lz4BlockEncoderPoolBase<C> {
+    /// Create a new [`Lz4BlockEncoderPoolBase`].
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    /// The checksum must return a [`u32`].
+    /// `c` is cloned for each new encoder.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    pub fn with_checksum(
+        c: C,
+        block_size: usize,
+        checksum: fn(&[u8]) -> u32,
+    ) -> std::io::Result<Self> {
+        Ok(Self {
+            context: c,
+            compression_level: CompressionLevel::from_block_size(block_size)?,
+            block_size,
+            checksum,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Get an idle encoder from the pool, or create a new one if there is none.
+    pub fn get(&self) -> Lz4BlockEncoderHandleBase<'_, C> {
+        let encoder = self.lock_idle().pop().unwrap_or_else(|| {
+            BlockEncoder::new(
+                self.context.clone(),
+                self.compression_level,
+                Checksum::new(self.checksum),
+            )
+        });
+        Lz4BlockEncoderHandleBase {
+            pool: self,
+            encoder: Some(encoder),
+        }
+    }
+}
+
+impl<C: Compression> Lz4BlockEncoderPoolBase<C> {
+    /// Get the number of encoders waiting in the pool.
+    pub fn idle_len(&self) -> usize {
+        self.lock_idle().len()
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<BlockEncoder<C>>> {
+        // the encoders stay valid even if a thread panicked while holding the lock
+        self.idle.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Default for Lz4BlockEncoderPool {
+    fn default() -> Self {
+        Self::new(Lz4BlockOutput::<Vec<u8>>::default_block_size()).unwrap()
+    }
+}
+
+/// An encoder borrowed from a [`Lz4BlockEncoderPool`], which goes back to the pool when dropped.
+pub type Lz4BlockEncoderHandle<'p> = Lz4BlockEncoderHandleBase<'p, Context>;
+
+/// An encoder borrowed from a [`Lz4BlockEncoderPoolBase`], which goes back to the pool when dropped.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockEncoderHandle`].
+#[derive(Debug)]
+pub struct Lz4BlockEncoderHandleBase<'p, C: Compression> {
+    pool: &'p Lz4BlockEncoderPoolBase<C>,
+    encoder: Option<BlockEncoder<C>>,
+}
+
+impl<C: Compression> Lz4BlockEncoderHandleBase<'_, C> {
+    /// Compress all of `data` to `writer`.
+    ///
+    /// It writes the same blocks as [`Lz4BlockOutput`] with the block size of the pool.
+    /// It can be called several times to write a single member, which is ended by [`Self::write_end()`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block could not be compressed or written.
+    pub fn compress_to<W: Write>(&mut self, data: &[u8], writer: &mut W) -> std::io::Result<()> {
+        Ok(Self::compress_to_inner(self, data, writer)?)
+    }
+
+    fn compress_to_inner<W: Write>(&mut self, data: &[u8], writer: &mut W) -> Result<()> {
+        let encoder = self.encoder.as_mut().unwrap();
+        for block in data.chunks(self.pool.block_size) {
+            encoder.encode_into(block, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write the empty block which ends a member, like [`Lz4BlockOutput::write_end()`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the block could not be written.
+    pub fn write_end<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        Ok(Self::write_end_inner(self, writer)?)
+    }
+
+    fn write_end_inner<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        let header = Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.pool.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        };
+        header.write(writer)?;
+        Ok(())
+    }
+}
+
+impl<C: Compression> Drop for Lz4BlockEncoderHandleBase<'_, C> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.encoder.take() {
+            self.pool.lock_idle().push(encoder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_encoder_pool {
+    use super::{Lz4BlockEncoderPool, Lz4BlockEncoderPoolBase};
+    use crate::compression::{Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::Lz4BlockHeader;
+    use crate::lz4_block_input::Lz4BlockInput;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{Read, Write};
+    use std::thread;
+
+    #[test]
+    fn pool_invalid_block_size() {
+        assert!(Lz4BlockEncoderPool::new(32).is_err());
+    }
+
+    #[test]
+    fn pool_same_as_lz4_block_output() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+
+        let pool = Lz4BlockEncoderPool::new(128).unwrap();
+        let mut out = Vec::<u8>::new();
+        pool.get().compress_to(&buf, &mut out).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn pool_write_end() {
+        let pool = Lz4BlockEncoderPool::new(64).unwrap();
+        let mut out = Vec::<u8>::new();
+        let mut encoder = pool.get();
+        encoder.compress_to("...".as_bytes(), &mut out).unwrap();
+        encoder.write_end(&mut out).unwrap();
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(out, expected);
+
+        drop(encoder);
+
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        let mut writer =
+            Lz4BlockOutput::with_context(&mut expected, Context::default(), 128).unwrap();
+        writer.write_all(&buf).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+        let pool = Lz4BlockEncoderPool::new(128).unwrap();
+        let mut out = Vec::<u8>::new();
+        let mut encoder = pool.get();
+        encoder.compress_to(&buf, &mut out).unwrap();
+        encoder.write_end(&mut out).unwrap();
+        assert_eq!(out, expected);
+
+        let mut decompressed = Vec::new();
+        Lz4BlockInput::new(&out[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, buf);
+    }
+
+    #[test]
+    fn pool_context_pair() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+
+        let pool = Lz4BlockEncoderPoolBase::with_checksum(
+            ContextPair::default(),
+            128,
+            Lz4BlockHeader::default_checksum,
+        )
+        .unwrap();
+        let mut out = Vec::<u8>::new();
+        pool.get().compress_to(&buf, &mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(pool.idle_len(), 1);
+    }
+
+    #[test]
+    fn pool_reuses_encoders() {
+        let pool = Lz4BlockEncoderPool::default();
+        assert_eq!(pool.idle_len(), 0);
+        {
+            let _first = pool.get();
+            let _second = pool.get();
+        }
+        assert_eq!(pool.idle_len(), 2);
+        let _handle = pool.get();
+        assert_eq!(pool.idle_len(), 1);
+    }
+
+    #[test]
+    fn pool_threads() {
+        let pool = Lz4BlockEncoderPool::new(128).unwrap();
+        let outputs: Vec<Vec<u8>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut out = Vec::new();
+                        pool.get().compress_to(&[b'.'; 1000], &mut out).unwrap();
+                        out
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(outputs.windows(2).all(|w| w[0] == w[1]));
+        assert!(pool.idle_len() >= 1);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::compression::Context;
+use crate::lz4_block_buf_input::Lz4BlockBufInput;
+use crate::lz4_block_output::Lz4BlockOutput;
+use crate::stats::{stat_seek, stat_seek_with_trailer, StreamStats};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Parameters of the blocks written by [`Lz4BlockFile`].
+#[derive(Debug, Clone, Copy)]
+pub struct Lz4BlockFileOptions {
+    /// The size of the blocks, between `64` and `33554432` bytes
+    pub block_size: usize,
+    /// The compression library
+    pub context: Context,
+    /// Whether each member ends with an XXH64 trailer, see [`Lz4BlockOutputBase::set_xxh64_trailer()`](crate::Lz4BlockOutputBase::set_xxh64_trailer)
+    pub xxh64_trailer: bool,
+}
+
+impl Default for Lz4BlockFileOptions {
+    fn default() -> Self {
+        Self {
+            block_size: Lz4BlockOutput::<Vec<u8>>::default_block_size(),
+            context: Context::default(),
+            xxh64_trailer: false,
+        }
+    }
+}
+
+/// A compressed file, opening the buffered readers and writers for it.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{Lz4BlockFile, Lz4BlockFileOptions};
+/// use std::io::{Read, Write};
+///
+/// fn main() -> std::io::Result<()> {
+///     let path = std::env::temp_dir().join("lz4_block_file_example.lz4");
+///     let mut file = Lz4BlockFile::create(&path, Lz4BlockFileOptions::default())?;
+///     file.writer()?.write_all("...".as_bytes())?;
+///
+///     let mut output = String::new();
+///     Lz4BlockFile::open(&path)?.reader()?.read_to_string(&mut output)?;
+///     println!("{}", output);
+///     std::fs::remove_file(path)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Lz4BlockFile {
+    path: PathBuf,
+    options: Lz4BlockFileOptions,
+    file: Option<BufWriter<File>>,
+}
+
+impl Lz4BlockFile {
+    /// Create the file at `path`, truncating it if it already exists.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the file could not be created.
+    pub fn create<P: AsRef<Path>>(path: P, options: Lz4BlockFileOptions) -> std::io::Result<Self> {
+        let file = File::create(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            options,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Open the existing file at `path`, with the default [`Lz4BlockFileOptions`].
+    ///
+    /// See [`Self::open_with_options()`]
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::open_with_options(path, Lz4BlockFileOptions::default())
+    }
+
+    /// Open the existing file at `path`.
+    ///
+    /// The `options` must match the ones the file was written with to read it.
+    /// The file is not kept open: each reader, writer and [`Self::stats()`] opens it when needed.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the file does not exist or its metadata could not be read.
+    pub fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        options: Lz4BlockFileOptions,
+    ) -> std::io::Result<Self> {
+        fs::metadata(path.as_ref())?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            options,
+            file: None,
+        })
+    }
+
+    /// Get the path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open a new reader decompressing the file from its start, with the [`Lz4BlockFileOptions::context`].
+    ///
+    /// The data still buffered by a writer is not visible.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the file could not be opened.
+    pub fn reader(&self) -> std::io::Result<Lz4BlockBufInput<BufReader<File>>> {
+        let mut reader = Lz4BlockBufInput::with_context(
+            BufReader::new(File::open(&self.path)?),
+            self.options.context,
+        );
+        reader.set_xxh64_trailer(self.options.xxh64_trailer);
+        Ok(reader)
+    }
+
+    /// Get a writer compressing data at the end of the file.
+    ///
+    /// The data is written to the file when the writer is flushed or dropped.
+    /// A file given by [`Self::open()`] is opened in append mode, so new blocks follow the existing ones.
+    /// With [`Lz4BlockFileOptions::xxh64_trailer`], each member must be ended by
+    /// [`Lz4BlockOutputBase::write_end()`](crate::Lz4BlockOutputBase::write_end).
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the file could not be opened, or if the block size is out of range.
+    pub fn writer(&mut self) -> std::io::Result<Lz4BlockOutput<'_, BufWriter<File>>> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            file => file.insert(BufWriter::new(
+                OpenOptions::new().append(true).open(&self.path)?,
+            )),
+        };
+        let mut writer =
+            Lz4BlockOutput::with_context(file, self.options.context, self.options.block_size)?;
+        writer.set_xxh64_trailer(self.options.xxh64_trailer);
+        Ok(writer)
+    }
+
+    /// Read the headers of the file, seeking over the data of the blocks.
+    ///
+    /// See [`stat_seek()`](crate::stat_seek) and [`stat_seek_with_trailer()`](crate::stat_seek_with_trailer)
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the file could not be read, or at the first invalid header.
+    pub fn stats(&self) -> std::io::Result<StreamStats> {
+        let file = File::open(&self.path)?;
+        if self.options.xxh64_trailer {
+            stat_seek_with_trailer(file)
+        } else {
+            stat_seek(file)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_file {
+    use super::{Lz4BlockFile, Lz4BlockFileOptions};
+    #[cfg(feature = "lz4-sys")]
+    use crate::compression::Context;
+
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("lz4_java_wrc_{}_{}.lz4", std::process::id(), name))
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = temp_path("round_trip");
+        let options = Lz4BlockFileOptions {
+            block_size: 128,
+            ..Lz4BlockFileOptions::default()
+        };
+        let mut file = Lz4BlockFile::create(&path, options).unwrap();
+        file.writer().unwrap().write_all(&[b'.'; 300]).unwrap();
+
+        let mut out = Vec::new();
+        file.reader().unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(out, [b'.'; 300]);
+        assert_eq!(file.stats().unwrap().blocks, 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "lz4-sys")]
+    #[test]
+    fn file_round_trip_lz4_sys() {
+        let path = temp_path("round_trip_lz4_sys");
+        let options = Lz4BlockFileOptions {
+            context: Context::Lz4Sys,
+            ..Lz4BlockFileOptions::default()
+        };
+        let mut file = Lz4BlockFile::create(&path, options).unwrap();
+        file.writer().unwrap().write_all(&[b'.'; 300]).unwrap();
+
+        let mut out = Vec::new();
+        Lz4BlockFile::open_with_options(&path, options)
+            .unwrap()
+            .reader()
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, [b'.'; 300]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_open_appends() {
+        let path = temp_path("open_appends");
+        Lz4BlockFile::create(&path, Lz4BlockFileOptions::default())
+            .unwrap()
+            .writer()
+            .unwrap()
+            .write_all(b"...")
+            .unwrap();
+        let mut file = Lz4BlockFile::open(&path).unwrap();
+        file.writer().unwrap().write_all(b"###").unwrap();
+
+        let mut out = Vec::new();
+        file.reader().unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"...###");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_with_trailer() {
+        let path = temp_path("with_trailer");
+        let options = Lz4BlockFileOptions {
+            xxh64_trailer: true,
+            ..Lz4BlockFileOptions::default()
+        };
+        let mut file = Lz4BlockFile::create(&path, options).unwrap();
+        for data in [b"...", b"###"] {
+            let mut writer = file.writer().unwrap();
+            writer.write_all(data).unwrap();
+            writer.write_end().unwrap();
+        }
+        let stats = file.stats().unwrap();
+        assert_eq!((stats.blocks, stats.empty_blocks), (4, 2));
+
+        let file = Lz4BlockFile::open_with_options(&path, options).unwrap();
+        let mut out = Vec::new();
+        let mut reader = file.reader().unwrap();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"...");
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"...###");
+
+        assert!(Lz4BlockFile::open(&path).unwrap().stats().is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_open_missing() {
+        assert!(Lz4BlockFile::open(temp_path("missing")).is_err());
+    }
+}
@@ -4,9 +4,11 @@ use crate::common::{
     IoErrorKind, Result,
 };
 
+#[cfg(not(feature = "xxhash-rust"))]
 use twox_hash::XxHash32;
 
 use std::convert::TryInto;
+#[cfg(not(feature = "xxhash-rust"))]
 use std::hash::Hasher;
 use std::io::{Read, Write};
 use std::ops::Range;
@@ -14,17 +16,19 @@ use std::result::Result as StdResult;
 
 const MAGIC_HEADER: &[u8; 8] = b"LZ4Block";
 const MAGIC_HEADER_RANGE: Range<usize> = 0..MAGIC_HEADER.len();
-const TOKEN_INDEX: usize = MAGIC_HEADER_RANGE.end;
+pub(crate) const TOKEN_INDEX: usize = MAGIC_HEADER_RANGE.end;
 const COMPRESSED_LEN_RANGE: Range<usize> = (TOKEN_INDEX + 1)..(TOKEN_INDEX + 5);
 const DECOMPRESSED_LEN_RANGE: Range<usize> =
     COMPRESSED_LEN_RANGE.end..(COMPRESSED_LEN_RANGE.end + 4);
 const CHECKSUM_RANGE: Range<usize> = DECOMPRESSED_LEN_RANGE.end..(DECOMPRESSED_LEN_RANGE.end + 4);
-const HEADER_LENGTH: usize = CHECKSUM_RANGE.end;
+pub(crate) const HEADER_LENGTH: usize = CHECKSUM_RANGE.end;
 
 const COMPRESSION_LEVEL_BASE: usize = 10;
 const MIN_BLOCK_SIZE: usize = 64;
 const MAX_BLOCK_SIZE: usize = 1 << (COMPRESSION_LEVEL_BASE + 0x0f);
 const DEFAULT_SEED: u32 = 0x9747b28c;
+/// The Java implementation drops the 4 first bits of the checksum
+pub(crate) const CHECKSUM_MASK: u32 = 0x0fffffff;
 const COMPRESSION_BLOCKS: [usize; 0x10] = [
     1 << (COMPRESSION_LEVEL_BASE + 14),
     1 << (COMPRESSION_LEVEL_BASE + 13),
@@ -44,6 +48,44 @@ const COMPRESSION_BLOCKS: [usize; 0x10] = [
     0,
 ];
 
+/// Metadata of a block from the compressed stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BlockInfo {
+    /// How the data of the block is stored
+    pub compression_method: CompressionMethod,
+    /// The compression level from the token: the block holds at most `1 << (10 + compression_level)` bytes
+    pub compression_level: u8,
+    /// The length of the data following the header
+    pub compressed_len: u32,
+    /// The length of the data once decompressed
+    pub decompressed_len: u32,
+    /// The checksum of the decompressed data
+    pub checksum: u32,
+    /// The position of the header in the compressed stream
+    pub offset: u64,
+}
+
+impl BlockInfo {
+    /// Get the header of the block, as written in the compressed stream.
+    ///
+    /// Only the 4 lowest bits of the compression level are kept, and the offset is ignored.
+    /// The values are not checked, so this can build invalid headers, for example to fuzz a reader.
+    pub fn to_header_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut buf = [0u8; HEADER_LENGTH];
+        Lz4BlockHeader {
+            compression_method: self.compression_method,
+            compression_level: CompressionLevel::from_token(self.compression_level),
+            compressed_len: self.compressed_len,
+            decompressed_len: self.decompressed_len,
+            checksum: self.checksum,
+        }
+        .write(&mut &mut buf[..])
+        .unwrap();
+        buf
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Lz4BlockHeader {
     pub(crate) compression_method: CompressionMethod,
@@ -53,18 +95,53 @@ pub(crate) struct Lz4BlockHeader {
     pub(crate) checksum: u32,
 }
 
+/// Compute the checksum of the decompressed data of a block, as written in the header by the Java implementation.
+///
+/// It is the xxhash32 seeded with `0x9747b28c`, without its 4 first bits.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::java_block_checksum;
+///
+/// assert_eq!(java_block_checksum(b"..."), 0x0677e452);
+/// ```
+pub fn java_block_checksum(buf: &[u8]) -> u32 {
+    Lz4BlockHeader::default_checksum(buf)
+}
+
 impl Lz4BlockHeader {
     /// Implement the java's default checksum implementation
     ///
     /// This implementation includes the bug around the missing 4 first bits.
     pub(crate) fn default_checksum(buf: &[u8]) -> u32 {
+        // Drop the 4 first bits: https://github.com/lz4/lz4-java/blob/1.8.0/src/java/net/jpountz/xxhash/StreamingXXHash32.java#L106
+        Self::unmasked_checksum(buf) & CHECKSUM_MASK
+    }
+
+    /// Compute the full xxhash32 behind [`Self::default_checksum()`], as stored by patched Java writers.
+    #[cfg(not(feature = "xxhash-rust"))]
+    pub(crate) fn unmasked_checksum(buf: &[u8]) -> u32 {
         let mut hasher = XxHash32::with_seed(DEFAULT_SEED);
         hasher.write(buf);
-        // Drop the 4 first bits: https://github.com/lz4/lz4-java/blob/1.8.0/src/java/net/jpountz/xxhash/StreamingXXHash32.java#L106
-        (hasher.finish() & 0x0fffffff) as u32
+        hasher.finish() as u32
+    }
+
+    /// Compute the full xxhash32 behind [`Self::default_checksum()`], as stored by patched Java writers.
+    #[cfg(feature = "xxhash-rust")]
+    pub(crate) fn unmasked_checksum(buf: &[u8]) -> u32 {
+        xxhash_rust::xxh32::xxh32(buf, DEFAULT_SEED)
     }
 
     pub(crate) fn read<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        match Self::read_bytes(reader)? {
+            None => Ok(None),
+            Some(header) => Ok(Some(Self::parse(&header)?)),
+        }
+    }
+
+    /// Read the bytes of a header without parsing them.
+    pub(crate) fn read_bytes<R: Read>(reader: &mut R) -> Result<Option<[u8; HEADER_LENGTH]>> {
         let mut header = [0u8; HEADER_LENGTH];
         if let Err(err) = reader.read_exact(&mut header[..]) {
             return if matches!(err.kind(), IoErrorKind::UnexpectedEof) {
@@ -73,7 +150,11 @@ impl Lz4BlockHeader {
                 Err(err.into())
             };
         }
-        Self::check_magic_header(&header)?;
+        Ok(Some(header))
+    }
+
+    pub(crate) fn parse(header: &[u8; HEADER_LENGTH]) -> Result<Self> {
+        Self::check_magic_header(header)?;
         let compression_method = CompressionMethod::from_token(header[TOKEN_INDEX])?;
         let compression_level = CompressionLevel::from_token(header[TOKEN_INDEX]);
         let compressed_len = u32::from_le_bytes(header[COMPRESSED_LEN_RANGE].try_into().unwrap());
@@ -101,13 +182,24 @@ impl Lz4BlockHeader {
         if compressed_len == 0 && decompressed_len == 0 && checksum != 0 {
             return ErrorChecksum::new_error(checksum, 0);
         }
-        Ok(Some(Self {
+        Ok(Self {
             compression_method,
             compression_level,
             compressed_len,
             decompressed_len,
             checksum,
-        }))
+        })
+    }
+
+    pub(crate) fn block_info(&self, offset: u64) -> BlockInfo {
+        BlockInfo {
+            compression_method: self.compression_method,
+            compression_level: self.compression_level.compression_level,
+            compressed_len: self.compressed_len,
+            decompressed_len: self.decompressed_len,
+            checksum: self.checksum,
+            offset,
+        }
     }
 
     pub(crate) fn write<W: Write>(&self, writer: &mut W) -> Result<usize> {
@@ -173,9 +265,13 @@ impl CompressionLevel {
 
 // CompressionMethod
 
-#[derive(Clone, Copy, Debug)]
-pub(crate) enum CompressionMethod {
+/// The way the data of a block is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum CompressionMethod {
+    /// The data is stored as is
     Raw = 1,
+    /// The data is compressed with LZ4
     Lz4 = 2,
 }
 
@@ -225,10 +321,30 @@ pub(crate) mod data {
         0x77,
         0x06,
         // data
-        '.' as u8,
-        '.' as u8,
-        '.' as u8,
+        b'.',
+        b'.',
+        b'.',
     ];
+    /// Generate data lz4 can't compress, using xorshift
+    pub(crate) fn incompressible(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    /// Generate 1000 bytes lz4 can't compress followed by 3000 bytes it can
+    pub(crate) fn mixed() -> Vec<u8> {
+        let mut data = incompressible(1000);
+        data.extend_from_slice(&[b'.'; 3000]);
+        data
+    }
+
     pub(crate) const VALID_EMPTY: [u8; HEADER_LENGTH] = [
         MAGIC_HEADER[0],
         MAGIC_HEADER[1],
@@ -262,13 +378,43 @@ pub(crate) mod data {
 mod test_lz4_block_header {
     use super::data::{VALID_DATA, VALID_EMPTY};
     use super::{
-        CompressionMethod, Lz4BlockHeader, DECOMPRESSED_LEN_RANGE, HEADER_LENGTH, TOKEN_INDEX,
+        BlockInfo, CompressionMethod, Lz4BlockHeader, DECOMPRESSED_LEN_RANGE, HEADER_LENGTH,
+        TOKEN_INDEX,
     };
 
     #[test]
     fn default_checksum_basic() {
         let mut v = VALID_DATA[HEADER_LENGTH..].to_vec();
         assert_eq!(Lz4BlockHeader::default_checksum(v.as_mut()), 0x0677e452);
+        assert_eq!(Lz4BlockHeader::unmasked_checksum(v.as_mut()), 0x7677e452);
+    }
+
+    #[test]
+    fn java_block_checksum_masked() {
+        for len in [0, 1, 3, 1000] {
+            let buf = super::data::incompressible(len);
+            let checksum = super::java_block_checksum(&buf);
+            assert_eq!(checksum, Lz4BlockHeader::default_checksum(&buf));
+            assert_eq!(checksum & !super::CHECKSUM_MASK, 0);
+        }
+    }
+
+    #[cfg(feature = "xxhash-rust")]
+    #[test]
+    fn default_checksum_same_as_twox_hash() {
+        use super::DEFAULT_SEED;
+        use std::hash::Hasher;
+        use twox_hash::XxHash32;
+
+        for len in [0, 1, 3, 15, 16, 17, 1000, 1 << 16] {
+            let buf = super::data::incompressible(len);
+            let mut hasher = XxHash32::with_seed(DEFAULT_SEED);
+            hasher.write(&buf);
+            assert_eq!(
+                Lz4BlockHeader::default_checksum(&buf),
+                (hasher.finish() & 0x0fffffff) as u32
+            );
+        }
     }
 
     #[test]
@@ -306,6 +452,41 @@ mod test_lz4_block_header {
         assert_eq!(header.checksum, 0x0677e452);
     }
 
+    #[test]
+    fn to_header_bytes() {
+        let mut d = &VALID_DATA[..];
+        let info = Lz4BlockHeader::read(&mut d)
+            .unwrap()
+            .unwrap()
+            .block_info(42);
+        assert_eq!(info.to_header_bytes(), VALID_DATA[..HEADER_LENGTH]);
+        let info = BlockInfo {
+            compression_level: 0x1a,
+            ..info
+        };
+        assert_eq!(info.to_header_bytes()[TOKEN_INDEX], 0x1a);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn to_header_bytes_arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let raw: Vec<u8> = (0..=255).collect();
+        let mut u = Unstructured::new(&raw);
+        while !u.is_empty() {
+            let info = BlockInfo::arbitrary(&mut u).unwrap();
+            // parsing arbitrary headers may fail, but never panic
+            if let Ok(header) = Lz4BlockHeader::parse(&info.to_header_bytes()) {
+                let expected = BlockInfo {
+                    compression_level: info.compression_level & 0x0f,
+                    ..info
+                };
+                assert_eq!(header.block_info(info.offset), expected);
+            }
+        }
+    }
+
     #[test]
     fn read_raw_different_sizes() {
         let mut v = VALID_DATA[..HEADER_LENGTH].to_vec();
@@ -366,7 +547,7 @@ mod test_compression_level {
                 i as u8
             );
             assert_eq!(
-                CompressionLevel::from_block_size(1 << (COMPRESSION_LEVEL_BASE + i) + 1)
+                CompressionLevel::from_block_size(1 << ((COMPRESSION_LEVEL_BASE + i) + 1))
                     .unwrap()
                     .compression_level,
                 (i + 1) as u8
@@ -1,9 +1,23 @@
-use crate::common::{Checksum, ErrorChecksum, ErrorLz4WrongDecompressedSize, Result};
+use crate::block_decoder::BlockDecoder;
+use crate::common::{
+    Checksum, Error, ErrorBlock, ErrorStreamHash, IoError, IoErrorKind, Observer, Result,
+};
 use crate::compression::{Compression, Context};
-use crate::lz4_block_header::{CompressionMethod, Lz4BlockHeader};
+use crate::lz4_block_header::{
+    BlockInfo, CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH,
+};
+use crate::stats::{StreamStats, TRAILER_LENGTH};
+
+use twox_hash::XxHash64;
 
 use std::cmp::min;
-use std::io::Read;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::Hasher;
+use std::io::{BufRead, Read};
+use std::mem;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::thread;
 
 /// Wrapper around a [`Read`] object to decompress data.
 ///
@@ -36,6 +50,41 @@ impl<R: Read> Lz4BlockInput<R> {
     pub fn new(r: R) -> Self {
         Self::with_context(r, Context::default())
     }
+
+    /// Create a new [`Lz4BlockInput`] whose buffers are allocated for blocks of `expected_block_size` bytes.
+    ///
+    /// It avoids the allocation when reading the first block. Bigger blocks are still accepted.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `expected_block_size` is not between `64` and `33554432` bytes.
+    pub fn with_capacity(r: R, expected_block_size: usize) -> std::io::Result<Self> {
+        let context = Context::default();
+        let max_decompressed_len = CompressionLevel::from_block_size(expected_block_size)?
+            .get_max_decompressed_buffer_len();
+        let mut input = Self::with_context(r, context);
+        input.core.compressed_buf =
+            Vec::with_capacity(context.get_maximum_compressed_buffer_len(max_decompressed_len));
+        input.core.decompressed_buf = Vec::with_capacity(max_decompressed_len);
+        Ok(input)
+    }
+}
+
+/// What [`Lz4BlockInputBase`] does when it reads an empty block.
+///
+/// The Java implementation writes an empty block when the stream is closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptyBlockPolicy {
+    #[default]
+    /// Stop reading at the first empty block, like the Java implementation does by default.
+    StopAtFirst,
+    /// Skip the empty blocks and read until the end of the wrapped [`Read`].
+    SkipAll,
+    /// Consider an empty block as the end of a member, which may be followed by another one.
+    ///
+    /// Reading stops cleanly if the data following the empty block is only zero bytes up to the end, such as padding.
+    /// Any other data must be a valid header.
+    TreatAsMemberBoundary,
 }
 
 /// Wrapper around a [`Read`] object to decompress data.
@@ -43,13 +92,8 @@ impl<R: Read> Lz4BlockInput<R> {
 /// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockInput`].
 #[derive(Debug)]
 pub struct Lz4BlockInputBase<R: Read + Sized, C: Compression> {
-    reader: R,
-    compression: C,
-    compressed_buf: Vec<u8>,
-    decompressed_buf: Vec<u8>,
-    read_ptr: usize,
-    checksum: Checksum,
-    stop_on_empty_block: bool,
+    reader: Unbuffered<R>,
+    core: InputCore<C>,
 }
 
 impl<R: Read, C: Compression> Lz4BlockInputBase<R, C> {
@@ -57,91 +101,511 @@ impl<R: Read, C: Compression> Lz4BlockInputBase<R, C> {
     ///
     /// See [`Self::with_checksum()`]
     pub fn with_context(r: R, c: C) -> Self {
-        Self::with_checksum(r, c, Lz4BlockHeader::default_checksum, true)
+        Self::with_checksum(
+            r,
+            c,
+            Lz4BlockHeader::default_checksum,
+            EmptyBlockPolicy::default(),
+        )
     }
 
     /// Create a new [`Lz4BlockInputBase`].
     ///
     /// The checksum must return a [`u32`].
+    /// The `empty_block_policy` tells what to do when reading an empty block.
     pub fn with_checksum(
         r: R,
         c: C,
         checksum: fn(&[u8]) -> u32,
-        stop_on_empty_block: bool,
+        empty_block_policy: EmptyBlockPolicy,
     ) -> Self {
         Self {
-            reader: r,
-            compression: c,
+            reader: Unbuffered(r),
+            core: InputCore::new(c, checksum, empty_block_policy),
+        }
+    }
+
+    /// Get the metadata of the block currently being read.
+    ///
+    /// It returns [`None`] until the first header has been read.
+    pub fn current_block(&self) -> Option<BlockInfo> {
+        self.core.current_block
+    }
+
+    /// Add the index and the offset of the block to the errors.
+    ///
+    /// The original error, such as a wrong checksum or a truncated stream, is kept as the [`source`](std::error::Error::source) of the returned error,
+    /// with the same [`ErrorKind`](std::io::ErrorKind).
+    pub fn set_diagnostics(&mut self, diagnostics: bool) {
+        self.core.diagnostics = diagnostics;
+    }
+
+    /// Also accept the blocks whose checksum in the header keeps the 4 first bits dropped by the Java implementation.
+    ///
+    /// Such streams are written by patched Java writers storing the full xxhash.
+    /// The block is accepted if its checksum is the full xxhash32 of the data, seeded like the Java implementation.
+    pub fn set_accept_unmasked_checksum(&mut self, accept_unmasked_checksum: bool) {
+        self.core
+            .decoder
+            .set_accept_unmasked_checksum(accept_unmasked_checksum);
+    }
+
+    /// Tell if a block with an unmasked checksum has been accepted so far.
+    ///
+    /// See [`Self::set_accept_unmasked_checksum()`]
+    pub fn unmasked_checksum_seen(&self) -> bool {
+        self.core.unmasked_checksum_seen
+    }
+
+    /// Reject the LZ4 blocks whose compressed length is bigger than what the [`Compression`] can produce for their decompressed length.
+    ///
+    /// It is enabled by default, so an untrusted stream can't make the reader allocate much more than the block size.
+    /// The Java implementation never writes such blocks, and the other decoders of this crate always reject them.
+    /// When disabled, the compressed length is trusted up to `i32::MAX` and the buffer is allocated accordingly.
+    pub fn set_strict_compressed_len(&mut self, strict_compressed_len: bool) {
+        self.core.strict_compressed_len = strict_compressed_len;
+    }
+
+    /// Check the XXH64 trailer following each empty block, as written by
+    /// [`Lz4BlockOutputBase::set_xxh64_trailer()`](crate::Lz4BlockOutputBase::set_xxh64_trailer).
+    ///
+    /// The trailer holds the XXH64 of the data decompressed since the previous trailer, so it also detects
+    /// the blocks dropped, duplicated or reordered, which keep a valid checksum each.
+    /// The stream must not end after a data block without its empty block and trailer.
+    ///
+    /// **Beware**: the streams written by the Java implementation have no trailer, so they are rejected with this option.
+    pub fn set_xxh64_trailer(&mut self, xxh64_trailer: bool) {
+        self.core.stream_hash = xxh64_trailer.then(|| XxHash64::with_seed(0));
+    }
+
+    /// Set a function called for each header read from the wrapped [`Read`], including the empty blocks.
+    ///
+    /// The [`BlockInfo::offset`] is the position of the block in the compressed stream.
+    pub fn set_observer<F: FnMut(&BlockInfo) + Send + 'static>(&mut self, observer: F) {
+        self.core.observer = Some(Observer::new(observer));
+    }
+
+    /// Get the number of members read so far, that is the number of empty blocks ending them.
+    pub fn members_read(&self) -> u64 {
+        self.core.members
+    }
+
+    /// Set a function called at the end of each member, after reading its empty block.
+    ///
+    /// It gets the offset in the compressed stream following the empty block, where the next member starts.
+    pub fn set_member_observer<F: FnMut(&u64) + Send + 'static>(&mut self, member_observer: F) {
+        self.core.member_observer = Some(Observer::new(member_observer));
+    }
+
+    pub(crate) fn stats(&self) -> StreamStats {
+        self.core.stats
+    }
+
+    /// Read and decompress the blocks on a background thread.
+    ///
+    /// While the caller consumes a block, the next one is read and decompressed.
+    /// It helps when reading from slow sources such as network filesystems.
+    pub fn into_prefetch(self) -> Lz4BlockInputPrefetch
+    where
+        R: Send + 'static,
+        C: Send + 'static,
+    {
+        Lz4BlockInputPrefetch::spawn(self.reader, self.core)
+    }
+
+    /// Get an iterator over the decompressed blocks, one item per block.
+    ///
+    /// The data already decompressed but not read yet is given first, as its own item.
+    /// Each item is copied into a new [`Vec`], use [`Self::next_block()`] to reuse the internal buffer instead.
+    /// The iterator stops after an error.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use lz4_java_wrc::Lz4BlockInput;
+    ///
+    /// const D: [u8; 24] = [
+    ///     76, 90, 52, 66, 108, 111, 99, 107, 16, 3, 0, 0, 0, 3, 0, 0, 0, 82, 228, 119, 6, 46, 46, 46,
+    /// ];
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     for block in Lz4BlockInput::new(&D[..]).blocks() {
+    ///         println!("{:?}", block?);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn blocks(&mut self) -> Lz4BlockInputBlocks<'_> {
+        Lz4BlockInputBlocks::new(self)
+    }
+
+    /// Decompress the next block into the internal buffer and lend it, or give the data not read yet.
+    ///
+    /// It returns [`None`] at the end of the stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use lz4_java_wrc::Lz4BlockInput;
+    ///
+    /// const D: [u8; 24] = [
+    ///     76, 90, 52, 66, 108, 111, 99, 107, 16, 3, 0, 0, 0, 3, 0, 0, 0, 82, 228, 119, 6, 46, 46, 46,
+    /// ];
+    ///
+    /// fn main() -> std::io::Result<()> {
+    ///     let mut input = Lz4BlockInput::new(&D[..]);
+    ///     while let Some(block) = input.next_block()? {
+    ///         println!("{:?}", block);
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the block could not be read or decompressed.
+    pub fn next_block(&mut self) -> std::io::Result<Option<&[u8]>> {
+        Ok(self.core.next_block(&mut self.reader)?)
+    }
+}
+
+impl<R: Read, C: Compression> NextBlock for Lz4BlockInputBase<R, C> {
+    fn next_block(&mut self) -> Result<Option<&[u8]>> {
+        self.core.next_block(&mut self.reader)
+    }
+}
+
+impl<R: Read, C: Compression> Read for Lz4BlockInputBase<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.core.read(&mut self.reader, buf)?)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        Ok(self.core.read_to_end(&mut self.reader, buf)?)
+    }
+}
+
+/// The wrapped reader of [`Lz4BlockInputBase`] or [`Lz4BlockBufInputBase`](crate::Lz4BlockBufInputBase).
+///
+/// The data of a block is parsed from the buffer of the reader when it holds it entirely, and copied otherwise.
+pub(crate) trait BlockSource: Read {
+    /// Get the next `len` bytes if they are already buffered, without consuming them.
+    fn buffered(&mut self, len: usize) -> Result<Option<&[u8]>>;
+
+    /// Consume the `len` bytes given by [`Self::buffered()`].
+    fn consume(&mut self, len: usize);
+}
+
+/// A [`Read`] without a buffer: the data is always copied.
+#[derive(Debug)]
+pub(crate) struct Unbuffered<R>(pub(crate) R);
+
+impl<R: Read> Read for Unbuffered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read> BlockSource for Unbuffered<R> {
+    fn buffered(&mut self, _len: usize) -> Result<Option<&[u8]>> {
+        Ok(None)
+    }
+
+    fn consume(&mut self, _len: usize) {}
+}
+
+/// A [`BufRead`] whose buffer is parsed directly.
+#[derive(Debug)]
+pub(crate) struct Buffered<R>(pub(crate) R);
+
+impl<R: BufRead> Read for Buffered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: BufRead> BlockSource for Buffered<R> {
+    fn buffered(&mut self, len: usize) -> Result<Option<&[u8]>> {
+        let available = self.0.fill_buf()?;
+        // the data may be split between several reads
+        Ok((available.len() >= len).then(|| &available[..len]))
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.0.consume(len);
+    }
+}
+
+/// The decoding state shared by [`Lz4BlockInputBase`] and [`Lz4BlockBufInputBase`](crate::Lz4BlockBufInputBase).
+///
+/// The wrapped reader is given to each call.
+#[derive(Debug)]
+pub(crate) struct InputCore<C: Compression> {
+    pub(crate) decoder: BlockDecoder<C>,
+    compressed_buf: Vec<u8>,
+    decompressed_buf: Vec<u8>,
+    read_ptr: usize,
+    empty_block_policy: EmptyBlockPolicy,
+    at_member_boundary: bool,
+    finished: bool,
+    offset: u64,
+    pub(crate) current_block: Option<BlockInfo>,
+    pub(crate) stats: StreamStats,
+    pub(crate) observer: Option<Observer>,
+    pub(crate) members: u64,
+    pub(crate) member_observer: Option<Observer<u64>>,
+    pub(crate) diagnostics: bool,
+    pub(crate) unmasked_checksum_seen: bool,
+    pub(crate) strict_compressed_len: bool,
+    pub(crate) stream_hash: Option<XxHash64>,
+    trailer_checked: bool,
+}
+
+impl<C: Compression> InputCore<C> {
+    pub(crate) fn new(
+        c: C,
+        checksum: fn(&[u8]) -> u32,
+        empty_block_policy: EmptyBlockPolicy,
+    ) -> Self {
+        Self {
+            decoder: BlockDecoder::new(c, Checksum::new(checksum)),
             compressed_buf: Vec::new(),
             decompressed_buf: Vec::new(),
             read_ptr: 0,
-            checksum: Checksum::new(checksum),
-            stop_on_empty_block,
+            empty_block_policy,
+            at_member_boundary: false,
+            finished: false,
+            offset: 0,
+            current_block: None,
+            stats: StreamStats::default(),
+            observer: None,
+            members: 0,
+            member_observer: None,
+            diagnostics: false,
+            unmasked_checksum_seen: false,
+            strict_compressed_len: true,
+            stream_hash: None,
+            trailer_checked: true,
+        }
+    }
+
+    fn read_trailer<S: BlockSource>(&mut self, reader: &mut S) -> Result<()> {
+        if let Some(stream_hash) = &mut self.stream_hash {
+            let mut trailer = [0u8; TRAILER_LENGTH];
+            reader.read_exact(&mut trailer)?;
+            self.offset += trailer.len() as u64;
+            self.stats.add_trailer();
+            let trailer_value = u64::from_le_bytes(trailer);
+            let computed_value = stream_hash.finish();
+            if trailer_value != computed_value {
+                return ErrorStreamHash::new_error(trailer_value, computed_value);
+            }
+            *stream_hash = XxHash64::with_seed(0);
+            self.trailer_checked = true;
+        }
+        Ok(())
+    }
+
+    fn block_error(&self, index: u64, offset: u64, err: Error) -> Error {
+        if self.diagnostics {
+            ErrorBlock::new(index, offset, err.into()).into()
+        } else {
+            err
         }
     }
 
-    fn read_header(&mut self) -> Result<Option<Lz4BlockHeader>> {
+    fn read_raw_header<S: BlockSource>(reader: &mut S) -> Result<Option<[u8; HEADER_LENGTH]>> {
+        if let Some(available) = reader.buffered(HEADER_LENGTH)? {
+            let header = available.try_into().unwrap();
+            reader.consume(HEADER_LENGTH);
+            return Ok(Some(header));
+        }
+        Lz4BlockHeader::read_bytes(reader)
+    }
+
+    fn read_header_bytes<S: BlockSource>(&mut self, reader: &mut S) -> Result<HeaderBytes> {
+        let header = match Self::read_raw_header(reader)? {
+            None => return Ok(HeaderBytes::Header(None)),
+            Some(header) => header,
+        };
+        if self.at_member_boundary && is_zero_padding(&header, reader)? {
+            return Ok(HeaderBytes::Padding);
+        }
+        Ok(HeaderBytes::Header(Some(Lz4BlockHeader::parse(&header)?)))
+    }
+
+    fn read_header<S: BlockSource>(&mut self, reader: &mut S) -> Result<Option<Lz4BlockHeader>> {
+        if self.finished {
+            return Ok(None);
+        }
         Ok(loop {
-            match Lz4BlockHeader::read(&mut self.reader)? {
+            let header = match self.read_header_bytes(reader) {
+                Err(err) => return Err(self.block_error(self.stats.blocks, self.offset, err)),
+                Ok(HeaderBytes::Padding) => {
+                    self.finished = true;
+                    break None;
+                }
+                Ok(HeaderBytes::Header(header)) => header,
+            };
+            match header {
+                None if !self.trailer_checked => {
+                    return Err(IoError::new(
+                        IoErrorKind::UnexpectedEof,
+                        "the stream ends without its XXH64 trailer",
+                    )
+                    .into())
+                }
                 None => break None,
                 Some(h) => {
+                    let block_info = h.block_info(self.offset);
+                    self.stats.add(&block_info);
+                    if let Some(observer) = &mut self.observer {
+                        observer.run(&block_info);
+                    }
+                    self.current_block = Some(block_info);
+                    self.offset += HEADER_LENGTH as u64;
                     if h.decompressed_len > 0 {
+                        self.at_member_boundary = false;
+                        self.trailer_checked = self.stream_hash.is_none();
                         break Some(h);
-                    } else if self.stop_on_empty_block {
-                        break None;
+                    }
+                    let block_offset = self.offset - HEADER_LENGTH as u64;
+                    self.read_trailer(reader).map_err(|err| {
+                        self.block_error(self.stats.blocks - 1, block_offset, err)
+                    })?;
+                    self.members += 1;
+                    if let Some(member_observer) = &mut self.member_observer {
+                        member_observer.run(&self.offset);
+                    }
+                    match self.empty_block_policy {
+                        EmptyBlockPolicy::StopAtFirst => break None,
+                        EmptyBlockPolicy::SkipAll => {}
+                        EmptyBlockPolicy::TreatAsMemberBoundary => self.at_member_boundary = true,
                     }
                 }
             };
         })
     }
 
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        if self.read_ptr == self.decompressed_buf.len() {
-            let header = match self.read_header()? {
-                None => return Ok(0),
-                Some(h) => h,
-            };
+    /// Decompress the next block into the internal buffer and lend it, or give the data not read yet.
+    pub(crate) fn next_block<S: BlockSource>(&mut self, reader: &mut S) -> Result<Option<&[u8]>> {
+        if self.read_ptr == self.decompressed_buf.len() && !self.fill_block(reader)? {
+            return Ok(None);
+        }
+        let start = self.read_ptr;
+        self.read_ptr = self.decompressed_buf.len();
+        Ok(Some(&self.decompressed_buf[start..]))
+    }
+
+    /// Read and decompress the next block into `decompressed_buf`.
+    ///
+    /// It returns `false` at the end of the stream.
+    fn fill_block<S: BlockSource>(&mut self, reader: &mut S) -> Result<bool> {
+        let header = match self.read_header(reader)? {
+            None => return Ok(false),
+            Some(h) => h,
+        };
 
-            ensure_vec(
-                &mut self.decompressed_buf,
-                header.compression_level.get_max_decompressed_buffer_len(),
-                header.decompressed_len,
-            );
-
-            match header.compression_method {
-                CompressionMethod::Raw => self.reader.read_exact(self.decompressed_buf.as_mut())?,
-                CompressionMethod::Lz4 => {
-                    ensure_vec(
-                        &mut self.compressed_buf,
-                        self.compression.get_maximum_compressed_buffer_len(
-                            header.compression_level.get_max_decompressed_buffer_len(),
-                        ),
-                        header.compressed_len,
-                    );
-                    self.reader.read_exact(self.compressed_buf.as_mut())?;
-                    match self
-                        .compression
-                        .decompress(self.compressed_buf.as_ref(), self.decompressed_buf.as_mut())
-                    {
-                        Ok(s) => {
-                            if s != self.decompressed_buf.len() {
-                                return ErrorLz4WrongDecompressedSize::new_error(
-                                    s,
-                                    self.decompressed_buf.len(),
-                                );
-                            }
-                        }
-                        Err(err) => {
-                            return Err(err.into());
-                        }
-                    };
+        let mut decompressed_buf = mem::take(&mut self.decompressed_buf);
+        ensure_vec(
+            &mut decompressed_buf,
+            header.compression_level.get_max_decompressed_buffer_len(),
+            header.decompressed_len,
+        );
+        let decoded = self.decode_block(reader, &header, decompressed_buf.as_mut());
+        self.decompressed_buf = decompressed_buf;
+        // the partially decoded block must not be read
+        self.read_ptr = match decoded {
+            Ok(()) => 0,
+            Err(_) => self.decompressed_buf.len(),
+        };
+        decoded?;
+        Ok(true)
+    }
+
+    /// Read the data following `header` and decompress it into `decompressed_buf`.
+    ///
+    /// `decompressed_buf` must have the decompressed length from the header.
+    fn decode_block<S: BlockSource>(
+        &mut self,
+        reader: &mut S,
+        header: &Lz4BlockHeader,
+        decompressed_buf: &mut [u8],
+    ) -> Result<()> {
+        let block_offset = self.offset - HEADER_LENGTH as u64;
+        self.decode_block_data(reader, header, decompressed_buf)
+            .map_err(|err| self.block_error(self.stats.blocks - 1, block_offset, err))?;
+        if let Some(stream_hash) = &mut self.stream_hash {
+            stream_hash.write(decompressed_buf);
+        }
+        Ok(())
+    }
+
+    fn decode_block_data<S: BlockSource>(
+        &mut self,
+        reader: &mut S,
+        header: &Lz4BlockHeader,
+        decompressed_buf: &mut [u8],
+    ) -> Result<()> {
+        if self.strict_compressed_len {
+            self.decoder.check_compressed_len(header)?;
+        }
+        let checked = match header.compression_method {
+            CompressionMethod::Raw => {
+                reader.read_exact(decompressed_buf)?;
+                self.decoder.check(header, decompressed_buf)
+            }
+            CompressionMethod::Lz4 => {
+                let compressed_len = header.compressed_len as usize;
+                match reader.buffered(compressed_len)? {
+                    Some(data) => {
+                        let decoded = self.decoder.decode_into(header, data, decompressed_buf);
+                        reader.consume(compressed_len);
+                        decoded
+                    }
+                    None => {
+                        ensure_vec(
+                            &mut self.compressed_buf,
+                            self.decoder.get_maximum_compressed_buffer_len(
+                                header.compression_level.get_max_decompressed_buffer_len(),
+                            ),
+                            header.compressed_len,
+                        );
+                        reader.read_exact(self.compressed_buf.as_mut())?;
+                        self.decoder
+                            .decode_into(header, &self.compressed_buf, decompressed_buf)
+                    }
                 }
             }
-            let computed_checksum = self.checksum.run(self.decompressed_buf.as_ref());
-            if computed_checksum != header.checksum {
-                return ErrorChecksum::new_error(header.checksum, computed_checksum);
+        };
+        self.offset += header.compressed_len as u64;
+        self.unmasked_checksum_seen |= checked?;
+        Ok(())
+    }
+
+    /// Decompress the remaining blocks directly at the end of `buf`, which grows by the decompressed length of each header.
+    pub(crate) fn read_to_end<S: BlockSource>(
+        &mut self,
+        reader: &mut S,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize> {
+        let start_len = buf.len();
+        buf.extend_from_slice(&self.decompressed_buf[self.read_ptr..]);
+        self.read_ptr = self.decompressed_buf.len();
+        while let Some(header) = self.read_header(reader)? {
+            let block_start = buf.len();
+            buf.resize(block_start + header.decompressed_len as usize, 0);
+            if let Err(err) = self.decode_block(reader, &header, &mut buf[block_start..]) {
+                buf.truncate(block_start);
+                return Err(err);
             }
-            self.read_ptr = 0;
+        }
+        Ok(buf.len() - start_len)
+    }
+
+    pub(crate) fn read<S: BlockSource>(&mut self, reader: &mut S, buf: &mut [u8]) -> Result<usize> {
+        if self.read_ptr == self.decompressed_buf.len() && !self.fill_block(reader)? {
+            return Ok(0);
         }
 
         let size_to_copy = min(buf.len(), self.decompressed_buf.len() - self.read_ptr);
@@ -152,27 +616,204 @@ impl<R: Read, C: Compression> Lz4BlockInputBase<R, C> {
     }
 }
 
-fn ensure_vec(v: &mut Vec<u8>, max_block_size: usize, desired_len: u32) {
-    let max_block_size = max_block_size;
-    if v.capacity() < max_block_size {
-        v.reserve(max_block_size - v.len())
+/// A reader lending its decompressed blocks, for [`Lz4BlockInputBlocks`].
+pub(crate) trait NextBlock {
+    fn next_block(&mut self) -> Result<Option<&[u8]>>;
+}
+
+/// Iterator over the decompressed blocks of a [`Lz4BlockInputBase`] or a [`Lz4BlockBufInputBase`](crate::Lz4BlockBufInputBase).
+///
+/// It is created with [`Lz4BlockInputBase::blocks()`].
+pub struct Lz4BlockInputBlocks<'a> {
+    input: &'a mut dyn NextBlock,
+    failed: bool,
+}
+
+impl<'a> Lz4BlockInputBlocks<'a> {
+    pub(crate) fn new(input: &'a mut dyn NextBlock) -> Self {
+        Self {
+            input,
+            failed: false,
+        }
     }
-    v.resize_with(desired_len as usize, u8::default);
 }
 
-impl<R: Read, C: Compression> Read for Lz4BlockInputBase<R, C> {
+impl fmt::Debug for Lz4BlockInputBlocks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lz4BlockInputBlocks")
+            .field("failed", &self.failed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Iterator for Lz4BlockInputBlocks<'_> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.input.next_block() {
+            Ok(block) => block.map(|block| Ok(block.to_vec())),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+/// Wrapper around a [`Lz4BlockInputBase`] reading the next block on a background thread.
+///
+/// It is created with [`Lz4BlockInputBase::into_prefetch()`] or [`Lz4BlockBufInputBase::into_prefetch()`](crate::Lz4BlockBufInputBase::into_prefetch).
+/// Dropping it lets the background thread stop once its current read returns.
+/// After an error, the next reads return an error of the same kind and message.
+/// If the background thread stops before the end of the stream, for example because of a panic,
+/// the reads fail instead of ending early.
+#[derive(Debug)]
+pub struct Lz4BlockInputPrefetch {
+    blocks: Receiver<Prefetched>,
+    recycle: Sender<Vec<u8>>,
+    block: Vec<u8>,
+    read_ptr: usize,
+    finished: bool,
+    error: Option<(IoErrorKind, String)>,
+}
+
+/// What the background thread of [`Lz4BlockInputPrefetch`] sends.
+#[derive(Debug)]
+enum Prefetched {
+    Block(Vec<u8>),
+    Error(IoError),
+    End,
+}
+
+impl Lz4BlockInputPrefetch {
+    pub(crate) fn spawn<S, C>(mut reader: S, mut core: InputCore<C>) -> Self
+    where
+        S: BlockSource + Send + 'static,
+        C: Compression + Send + 'static,
+    {
+        // one block ready in the channel while the next one is being read
+        let (blocks_sender, blocks) = sync_channel(1);
+        let (recycle, recycled) = channel::<Vec<u8>>();
+        thread::spawn(move || loop {
+            let prefetched = match core.fill_block(&mut reader) {
+                Ok(true) => {
+                    let spare = recycled.try_recv().unwrap_or_default();
+                    Prefetched::Block(mem::replace(&mut core.decompressed_buf, spare))
+                }
+                Ok(false) => Prefetched::End,
+                Err(err) => Prefetched::Error(err.into()),
+            };
+            let last = !matches!(prefetched, Prefetched::Block(_));
+            if blocks_sender.send(prefetched).is_err() || last {
+                break;
+            }
+        });
+        Self {
+            blocks,
+            recycle,
+            block: Vec::new(),
+            read_ptr: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        Ok(Self::read(self, buf)?)
+        if let Some((kind, message)) = &self.error {
+            // the background thread stopped after sending the error
+            return Err(IoError::new(*kind, message.clone()));
+        }
+        if self.read_ptr == self.block.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            let block = match self.blocks.recv() {
+                Ok(Prefetched::Block(block)) => block,
+                Ok(Prefetched::End) => {
+                    self.finished = true;
+                    return Ok(0);
+                }
+                Ok(Prefetched::Error(err)) => {
+                    self.error = Some((err.kind(), err.to_string()));
+                    return Err(err);
+                }
+                Err(_) => {
+                    let err =
+                        IoError::other("the prefetch thread stopped before the end of the stream");
+                    self.error = Some((err.kind(), err.to_string()));
+                    return Err(err);
+                }
+            };
+            let _ = self.recycle.send(mem::replace(&mut self.block, block));
+            self.read_ptr = 0;
+        }
+
+        let size_to_copy = min(buf.len(), self.block.len() - self.read_ptr);
+        buf[..size_to_copy]
+            .copy_from_slice(&self.block[self.read_ptr..self.read_ptr + size_to_copy]);
+        self.read_ptr += size_to_copy;
+        Ok(size_to_copy)
     }
 }
 
+impl Read for Lz4BlockInputPrefetch {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Self::read(self, buf)
+    }
+}
+
+/// What follows an empty block when it is treated as a member boundary.
+enum HeaderBytes {
+    /// Zero bytes up to the end of the stream
+    Padding,
+    /// The header of the next block, if any
+    Header(Option<Lz4BlockHeader>),
+}
+
+/// Tell if `bytes` and the rest of `reader` are only zero bytes.
+///
+/// The reader is consumed up to the end, or up to the first non-zero byte.
+fn is_zero_padding<R: Read>(bytes: &[u8], reader: &mut R) -> Result<bool> {
+    if bytes.iter().any(|b| *b != 0) {
+        return Ok(false);
+    }
+    let mut buf = [0u8; 512];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(len) => len,
+            Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if len == 0 {
+            return Ok(true);
+        }
+        if buf[..len].iter().any(|b| *b != 0) {
+            return Ok(false);
+        }
+    }
+}
+
+pub(crate) fn ensure_vec(v: &mut Vec<u8>, max_block_size: usize, desired_len: u32) {
+    if v.capacity() < max_block_size {
+        v.reserve(max_block_size - v.len())
+    }
+    v.resize_with(desired_len as usize, u8::default);
+}
+
 #[cfg(test)]
 mod test_lz4_block_input {
-    use super::Lz4BlockInput;
+    use super::{EmptyBlockPolicy, Lz4BlockInput};
     use crate::compression::Context;
-    use crate::lz4_block_header::data::{VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::data::{incompressible, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::{BlockInfo, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+    use crate::lz4_block_output::Lz4BlockOutput;
+    use crate::{Lz4BlockBufInput, Lz4BlockDecompressor, Lz4BlockPushDecoder, MessageCodec};
 
-    use std::io::Read;
+    use std::io::{Cursor, ErrorKind, Read, Write};
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn read_empty() {
@@ -199,7 +840,7 @@ mod test_lz4_block_input {
             &VALID_DATA[..],
             Context::default(),
             |_| 0x12345678,
-            true
+            EmptyBlockPolicy::StopAtFirst
         )
         .read_to_end(&mut out)
         .is_err());
@@ -208,9 +849,14 @@ mod test_lz4_block_input {
     #[test]
     fn read_with_checksum_valid() {
         let mut out = Vec::<u8>::new();
-        Lz4BlockInput::with_checksum(&VALID_DATA[..], Context::default(), |_| 0x0677e452, true)
-            .read_to_end(&mut out)
-            .unwrap();
+        Lz4BlockInput::with_checksum(
+            &VALID_DATA[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::StopAtFirst,
+        )
+        .read_to_end(&mut out)
+        .unwrap();
         assert_eq!(out, "...".as_bytes());
     }
 
@@ -220,9 +866,14 @@ mod test_lz4_block_input {
         input.extend_from_slice(&[0; 21]);
 
         let mut out = Vec::<u8>::new();
-        Lz4BlockInput::with_checksum(&input[..], Context::default(), |_| 0x0677e452, true)
-            .read_to_end(&mut out)
-            .unwrap();
+        Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::StopAtFirst,
+        )
+        .read_to_end(&mut out)
+        .unwrap();
         assert_eq!(out, "".as_bytes());
     }
 
@@ -234,9 +885,14 @@ mod test_lz4_block_input {
         input.extend_from_slice(&VALID_EMPTY);
 
         let mut out = Vec::<u8>::new();
-        Lz4BlockInput::with_checksum(&input[..], Context::default(), |_| 0x0677e452, false)
-            .read_to_end(&mut out)
-            .unwrap();
+        Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::SkipAll,
+        )
+        .read_to_end(&mut out)
+        .unwrap();
         assert_eq!(out, "".as_bytes());
     }
 
@@ -253,9 +909,608 @@ mod test_lz4_block_input {
             &input[..],
             Context::default(),
             |_| 0x0677e452,
-            false
+            EmptyBlockPolicy::SkipAll
+        )
+        .read_to_end(&mut out)
+        .is_err());
+    }
+
+    #[test]
+    fn read_with_member_boundary() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        input.extend_from_slice(&VALID_EMPTY);
+
+        let mut out = Vec::<u8>::new();
+        Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        )
+        .read_to_end(&mut out)
+        .unwrap();
+        assert_eq!(out, "......".as_bytes());
+    }
+
+    #[test]
+    fn read_with_member_boundary_padding() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&[0; 64]);
+
+        let mut out = Vec::<u8>::new();
+        Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
         )
         .read_to_end(&mut out)
+        .unwrap();
+        assert_eq!(out, "...".as_bytes());
+    }
+
+    #[test]
+    fn read_with_member_boundary_corrupted() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        // flip a byte of the magic number of the second member
+        input[VALID_DATA.len() + VALID_EMPTY.len()] ^= 1;
+
+        let mut reader = Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        );
+        let err = Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_with_member_boundary_padding_not_zero() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&[0; 600]);
+        input.push(1);
+
+        assert!(Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        )
+        .read_to_end(&mut Vec::new())
         .is_err());
     }
+
+    #[test]
+    fn read_with_member_boundary_error() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&[0; 64]);
+
+        let mut out = Vec::<u8>::new();
+        assert!(Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        )
+        .read_to_end(&mut out)
+        .is_err());
+    }
+
+    #[test]
+    fn read_to_end_after_read() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        let mut buf = [0u8; 1];
+        reader.read_exact(&mut buf).unwrap();
+        let mut out = b"#".to_vec();
+        assert_eq!(reader.read_to_end(&mut out).unwrap(), 5);
+        assert_eq!(out, "#.....".as_bytes());
+    }
+
+    #[test]
+    fn read_to_end_error_keeps_valid_blocks() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        // corrupt the data of the second block
+        *input.last_mut().unwrap() = b'#';
+
+        let mut out = Vec::<u8>::new();
+        assert!(Lz4BlockInput::new(&input[..])
+            .read_to_end(&mut out)
+            .is_err());
+        assert_eq!(out, "...".as_bytes());
+    }
+
+    #[test]
+    fn blocks_basic() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+
+        let blocks: Vec<Vec<u8>> = Lz4BlockInput::new(&input[..])
+            .blocks()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(blocks, [b"...", b"..."]);
+    }
+
+    #[test]
+    fn blocks_after_read() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        let mut buf = [0u8; 1];
+        Read::read_exact(&mut reader, &mut buf).unwrap();
+        let blocks: Vec<Vec<u8>> = reader.blocks().map(Result::unwrap).collect();
+        assert_eq!(blocks, [&b".."[..], &b"..."[..]]);
+    }
+
+    #[test]
+    fn next_block_reuses_buffer() {
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 128)
+            .unwrap()
+            .write_all(&incompressible(300))
+            .unwrap();
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        let mut buf = [0u8; 100];
+        Read::read_exact(&mut reader, &mut buf).unwrap();
+        let mut blocks = vec![buf.to_vec()];
+        let mut pointers = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            pointers.push(block.as_ptr());
+            blocks.push(block.to_vec());
+        }
+        assert_eq!(
+            blocks.iter().map(Vec::len).collect::<Vec<_>>(),
+            [100, 28, 128, 44]
+        );
+        assert_eq!(blocks.concat(), incompressible(300));
+        // the remaining data of the first block, then the start of the same buffer
+        assert!(pointers[1..].iter().all(|p| *p == pointers[1]));
+    }
+
+    #[test]
+    fn next_block_error() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        *input.last_mut().unwrap() = b'#';
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        assert_eq!(reader.next_block().unwrap(), Some(&b"..."[..]));
+        assert!(reader.next_block().is_err());
+        // the block failing its checksum is not given
+        assert!(reader.next_block().unwrap().is_none());
+    }
+
+    #[test]
+    fn blocks_error() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        *input.last_mut().unwrap() = b'#';
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        let mut blocks = reader.blocks();
+        assert_eq!(blocks.next().unwrap().unwrap(), b"...");
+        assert_eq!(
+            blocks.next().unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(blocks.next().is_none());
+    }
+
+    #[test]
+    fn read_with_capacity() {
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 1024)
+            .unwrap()
+            .write_all(&[b'.'; 4000])
+            .unwrap();
+        let mut reader = Lz4BlockInput::with_capacity(&input[..], 1024).unwrap();
+        let capacities = (
+            reader.core.compressed_buf.capacity(),
+            reader.core.decompressed_buf.capacity(),
+        );
+        assert!(capacities.1 >= 1024);
+        let mut out = Vec::<u8>::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, [b'.'; 4000]);
+        assert_eq!(
+            (
+                reader.core.compressed_buf.capacity(),
+                reader.core.decompressed_buf.capacity()
+            ),
+            capacities
+        );
+
+        assert!(Lz4BlockInput::with_capacity(&input[..], 1).is_err());
+    }
+
+    fn with_trailer(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::<u8>::new();
+        let mut writer =
+            Lz4BlockOutput::with_context(&mut compressed, Context::default(), 64).unwrap();
+        writer.set_xxh64_trailer(true);
+        writer.write_all(data).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+        compressed
+    }
+
+    fn read_with_trailer(input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut reader = Lz4BlockInput::new(input);
+        reader.set_xxh64_trailer(true);
+        let mut out = Vec::<u8>::new();
+        Read::read_to_end(&mut reader, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn read_xxh64_trailer() {
+        let input = with_trailer(b"...");
+        assert_eq!(input.len(), VALID_DATA.len() + VALID_EMPTY.len() + 8);
+        assert_eq!(read_with_trailer(&input).unwrap(), b"...");
+
+        // the trailer is ignored when the option is disabled, like in Java
+        let mut out = Vec::<u8>::new();
+        Lz4BlockInput::new(&input[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"...");
+    }
+
+    #[test]
+    fn read_xxh64_trailer_members() {
+        let mut input = with_trailer(b"...");
+        input.extend_from_slice(&with_trailer(b"###"));
+        for empty_block_policy in [
+            EmptyBlockPolicy::SkipAll,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        ] {
+            let read = |xxh64_trailer: bool| {
+                let mut reader = Lz4BlockInput::with_checksum(
+                    &input[..],
+                    Context::default(),
+                    Lz4BlockHeader::default_checksum,
+                    empty_block_policy,
+                );
+                reader.set_xxh64_trailer(xxh64_trailer);
+                let mut out = Vec::<u8>::new();
+                Read::read_to_end(&mut reader, &mut out).map(|_| (out, reader.members_read()))
+            };
+            assert_eq!(read(true).unwrap(), (b"...###".to_vec(), 2));
+            // the trailer of the first member is read as the next header
+            assert_eq!(read(false).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn read_xxh64_trailer_invalid() {
+        let mut input = with_trailer(b"...");
+        *input.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            read_with_trailer(&input).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        input.pop();
+        assert_eq!(
+            read_with_trailer(&input).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            read_with_trailer(&VALID_DATA).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn read_xxh64_trailer_reordered() {
+        let data = incompressible(128);
+        let mut input = with_trailer(&data);
+        // swap the two raw blocks, each one keeping a valid checksum
+        let block_len = HEADER_LENGTH + 64;
+        let (first, second) = input[..2 * block_len].split_at_mut(block_len);
+        first.swap_with_slice(second);
+        let mut out = Vec::<u8>::new();
+        Lz4BlockInput::new(&input[..])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_ne!(out, data);
+        assert_eq!(
+            read_with_trailer(&input).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn read_prefetch() {
+        let mut input = Vec::new();
+        for _ in 0..100 {
+            input.extend_from_slice(&VALID_DATA);
+        }
+
+        let mut out = Vec::<u8>::new();
+        Lz4BlockInput::new(Cursor::new(input))
+            .into_prefetch()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, "...".repeat(100).as_bytes());
+    }
+
+    #[test]
+    fn read_prefetch_error() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&[0; 21]);
+
+        let mut out = Vec::<u8>::new();
+        assert!(Lz4BlockInput::new(Cursor::new(input))
+            .into_prefetch()
+            .read_to_end(&mut out)
+            .is_err());
+        assert_eq!(out, "...".as_bytes());
+    }
+
+    #[test]
+    fn read_prefetch_error_kept() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&[0; 21]);
+
+        let mut reader = Lz4BlockInput::new(Cursor::new(input)).into_prefetch();
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).unwrap();
+        let err = Read::read(&mut reader, &mut buf).unwrap_err();
+        for _ in 0..3 {
+            let again = Read::read(&mut reader, &mut buf).unwrap_err();
+            assert_eq!(again.kind(), err.kind());
+            assert_eq!(again.to_string(), err.to_string());
+        }
+    }
+
+    #[test]
+    fn read_prefetch_error_source() {
+        let mut input = VALID_DATA.to_vec();
+        *input.last_mut().unwrap() = b'#';
+
+        let mut reader = Lz4BlockInput::new(Cursor::new(input));
+        reader.set_diagnostics(true);
+        let mut reader = reader.into_prefetch();
+        let err = Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // the error of the background thread is given as is
+        assert!(err.get_ref().unwrap().source().is_some());
+    }
+
+    struct PanicRead;
+
+    impl Read for PanicRead {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            panic!("the reader panics");
+        }
+    }
+
+    #[test]
+    fn read_prefetch_panic() {
+        let input = Cursor::new(VALID_DATA.to_vec()).chain(PanicRead);
+        let mut reader = Lz4BlockInput::new(input).into_prefetch();
+        let mut out = Vec::<u8>::new();
+        let err = reader.read_to_end(&mut out).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(out, "...".as_bytes());
+        assert_eq!(
+            Read::read(&mut reader, &mut [0u8; 3]).unwrap_err().kind(),
+            ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn observer_blocks() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+
+        let offsets = Arc::new(Mutex::new(Vec::new()));
+        let observed = offsets.clone();
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_observer(move |block| observed.lock().unwrap().push(block.offset));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(*offsets.lock().unwrap(), [0, VALID_DATA.len() as u64]);
+    }
+
+    #[test]
+    fn current_block_none() {
+        let reader = Lz4BlockInput::new(&VALID_DATA[..]);
+        assert_eq!(reader.current_block(), None);
+    }
+
+    #[test]
+    fn current_block_offset() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        let mut buf = [0u8; 3];
+        reader.read_exact(&mut buf).unwrap();
+        let first = reader.current_block().unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        let second = reader.current_block().unwrap();
+
+        assert_eq!(
+            first,
+            BlockInfo {
+                compression_method: CompressionMethod::Raw,
+                compression_level: 0,
+                compressed_len: 3,
+                decompressed_len: 3,
+                checksum: 0x0677e452,
+                offset: 0,
+            }
+        );
+        assert_eq!(second.offset, VALID_DATA.len() as u64);
+    }
+
+    #[test]
+    fn diagnostics_checksum() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        *input.last_mut().unwrap() = b'#';
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_diagnostics(true);
+        let err = Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with(&format!(
+            "block 1 at offset {}: wrong checksum",
+            VALID_DATA.len()
+        )));
+        assert!(err.get_ref().unwrap().source().is_some());
+    }
+
+    #[test]
+    fn diagnostics_header() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&[0; 21]);
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_diagnostics(true);
+        let err = Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().starts_with(&format!(
+            "block 1 at offset {}: wrong magic number",
+            VALID_DATA.len()
+        )));
+    }
+
+    #[test]
+    fn diagnostics_truncated() {
+        let input = &VALID_DATA[..VALID_DATA.len() - 1];
+
+        let mut reader = Lz4BlockInput::new(input);
+        reader.set_diagnostics(true);
+        let err = Read::read_to_end(&mut reader, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(err.to_string().starts_with("block 0 at offset 0: "));
+    }
+
+    #[test]
+    fn diagnostics_disabled() {
+        let input = &VALID_DATA[..VALID_DATA.len() - 1];
+
+        let err = Read::read_to_end(&mut Lz4BlockInput::new(input), &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(!err.to_string().starts_with("block"));
+    }
+
+    #[test]
+    fn members_boundaries() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        input.extend_from_slice(&VALID_DATA);
+        input.extend_from_slice(&VALID_EMPTY);
+
+        let offsets = Arc::new(Mutex::new(Vec::new()));
+        let observed = offsets.clone();
+        let mut reader = Lz4BlockInput::with_checksum(
+            &input[..],
+            Context::default(),
+            |_| 0x0677e452,
+            EmptyBlockPolicy::TreatAsMemberBoundary,
+        );
+        reader.set_member_observer(move |offset| observed.lock().unwrap().push(*offset));
+        assert_eq!(reader.members_read(), 0);
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(reader.members_read(), 2);
+        let first_member_len = (VALID_DATA.len() + VALID_EMPTY.len()) as u64;
+        assert_eq!(
+            *offsets.lock().unwrap(),
+            [first_member_len, input.len() as u64]
+        );
+    }
+
+    fn unmasked_input() -> Vec<u8> {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        // the full xxhash of "...", whose 4 first bits are dropped by the Java implementation
+        input[VALID_DATA.len() + 17..VALID_DATA.len() + 21]
+            .copy_from_slice(&0x7677e452u32.to_le_bytes());
+        input
+    }
+
+    #[test]
+    fn read_unmasked_checksum_rejected() {
+        assert!(Lz4BlockInput::new(&unmasked_input()[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn read_unmasked_checksum_accepted() {
+        let input = unmasked_input();
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_accept_unmasked_checksum(true);
+        let mut out = Vec::<u8>::new();
+        reader.read_exact(&mut [0u8; 3]).unwrap();
+        assert!(!reader.unmasked_checksum_seen());
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, "...".as_bytes());
+        assert!(reader.unmasked_checksum_seen());
+    }
+
+    #[test]
+    fn read_unmasked_checksum_wrong_bits() {
+        let mut input = unmasked_input();
+        // the 28 last bits still match, but not the 4 first ones
+        input[VALID_DATA.len() + 20] = 0x96;
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_accept_unmasked_checksum(true);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(!reader.unmasked_checksum_seen());
+    }
+
+    #[test]
+    fn read_strict_compressed_len() {
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::new(&mut input)
+            .write_all(&[b'.'; 100])
+            .unwrap();
+        // pretend that the block is much bigger than it could be
+        input[9..13].copy_from_slice(&1000u32.to_le_bytes());
+        input.resize(HEADER_LENGTH + 1000, 0);
+
+        let err = Lz4BlockInput::new(&input[..])
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("compressed size: 1000"));
+
+        let mut reader = Lz4BlockInput::new(&input[..]);
+        reader.set_strict_compressed_len(false);
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(!err.to_string().contains("compressed size"));
+
+        // the other decoders apply the same rule
+        let errors = [
+            Lz4BlockBufInput::new(&input[..])
+                .read_to_end(&mut Vec::new())
+                .unwrap_err(),
+            Lz4BlockPushDecoder::new().push_bytes(&input).unwrap_err(),
+            Lz4BlockDecompressor::new()
+                .decompress(&input, &mut [0u8; 100])
+                .unwrap_err(),
+            MessageCodec::default().decode(&input).unwrap_err(),
+        ];
+        for err in errors {
+            assert!(err.to_string().contains("compressed size: 1000"), "{}", err);
+        }
+    }
 }
@@ -0,0 +1,419 @@
+use crate::lz4_block_body::{BoxError, Lz4BlockDecodeBody, Lz4BlockEncodeBody};
+
+use bytes::{Buf, Bytes};
+use http::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY};
+use http::{HeaderValue, Method, Request, Response, StatusCode};
+use http_body::{Body, Frame};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+
+/// The `Content-Encoding` of the bodies handled by [`Lz4BlockLayer`].
+pub const LZ4_BLOCK_ENCODING: &str = "lz4-block";
+
+/// A [`Layer`] decompressing the requests and compressing the responses using the [`LZ4_BLOCK_ENCODING`].
+///
+/// The requests are decompressed when their `Content-Encoding` is [`LZ4_BLOCK_ENCODING`].
+/// The responses are compressed when the `Accept-Encoding` of the request accepts [`LZ4_BLOCK_ENCODING`],
+/// by name or with `*`, and not with a zero quality. It is not done if they already have a `Content-Encoding`
+/// or they have no body: the responses to `HEAD` requests, and the ones with a `1xx`, `204 No Content`
+/// or `304 Not Modified` status. The other responses get `Vary: Accept-Encoding`, whether they are compressed or not,
+/// so the shared caches keep them apart.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4BlockLayer;
+
+impl<S> Layer<S> for Lz4BlockLayer {
+    type Service = Lz4BlockService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Lz4BlockService { inner }
+    }
+}
+
+/// The [`Service`] created by [`Lz4BlockLayer`].
+#[derive(Debug, Clone)]
+pub struct Lz4BlockService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Lz4BlockService<S>
+where
+    S: Service<Request<Lz4BlockMaybeBody<ReqBody>>, Response = Response<ResBody>>,
+    ReqBody: Body + Unpin,
+    ResBody: Body + Unpin,
+{
+    type Response = Response<Lz4BlockMaybeBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Lz4BlockResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let negotiate = req.method() != Method::HEAD;
+        let accepted = accepts_lz4_block(req.headers());
+        let (mut parts, body) = req.into_parts();
+        let body = if is_lz4_block(&parts.headers) {
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            Lz4BlockMaybeBody::decoded(body)
+        } else {
+            Lz4BlockMaybeBody::plain(body)
+        };
+        Lz4BlockResponseFuture {
+            inner: Box::pin(self.inner.call(Request::from_parts(parts, body))),
+            negotiate,
+            accepted,
+        }
+    }
+}
+
+/// The [`Future`] returned by [`Lz4BlockService`].
+pub struct Lz4BlockResponseFuture<F> {
+    inner: Pin<Box<F>>,
+    negotiate: bool,
+    accepted: bool,
+}
+
+impl<F, ResBody, E> Future for Lz4BlockResponseFuture<F>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    ResBody: Body + Unpin,
+{
+    type Output = Result<Response<Lz4BlockMaybeBody<ResBody>>, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let (negotiate, accepted) = (self.negotiate, self.accepted);
+        let (mut parts, body) = ready!(self.inner.as_mut().poll(cx))?.into_parts();
+        let negotiate =
+            negotiate && has_body(parts.status) && !parts.headers.contains_key(CONTENT_ENCODING);
+        if negotiate {
+            add_vary_accept_encoding(&mut parts.headers);
+        }
+        let body = if negotiate && accepted {
+            parts.headers.insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(LZ4_BLOCK_ENCODING),
+            );
+            parts.headers.remove(CONTENT_LENGTH);
+            Lz4BlockMaybeBody::encoded(body)
+        } else {
+            Lz4BlockMaybeBody::plain(body)
+        };
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
+
+impl<F> std::fmt::Debug for Lz4BlockResponseFuture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lz4BlockResponseFuture")
+            .field("negotiate", &self.negotiate)
+            .field("accepted", &self.accepted)
+            .finish()
+    }
+}
+
+/// A [`Body`] given by [`Lz4BlockService`], which may have been decompressed or compressed.
+#[derive(Debug)]
+pub struct Lz4BlockMaybeBody<B> {
+    kind: MaybeBodyKind<B>,
+}
+
+#[derive(Debug)]
+enum MaybeBodyKind<B> {
+    Plain(B),
+    Decoded(Lz4BlockDecodeBody<B>),
+    Encoded(Lz4BlockEncodeBody<B>),
+}
+
+impl<B: Body + Unpin> Lz4BlockMaybeBody<B> {
+    fn plain(body: B) -> Self {
+        Self {
+            kind: MaybeBodyKind::Plain(body),
+        }
+    }
+
+    fn decoded(body: B) -> Self {
+        Self {
+            kind: MaybeBodyKind::Decoded(Lz4BlockDecodeBody::new(body)),
+        }
+    }
+
+    fn encoded(body: B) -> Self {
+        Self {
+            kind: MaybeBodyKind::Encoded(Lz4BlockEncodeBody::new(body)),
+        }
+    }
+}
+
+impl<B> Body for Lz4BlockMaybeBody<B>
+where
+    B: Body + Unpin,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match &mut self.kind {
+            MaybeBodyKind::Plain(body) => Pin::new(body).poll_frame(cx).map(|frame| {
+                frame.map(|frame| {
+                    frame
+                        .map(|frame| {
+                            frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+                        })
+                        .map_err(Into::into)
+                })
+            }),
+            MaybeBodyKind::Decoded(body) => Pin::new(body).poll_frame(cx),
+            MaybeBodyKind::Encoded(body) => Pin::new(body).poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            MaybeBodyKind::Plain(body) => body.is_end_stream(),
+            MaybeBodyKind::Decoded(body) => body.is_end_stream(),
+            MaybeBodyKind::Encoded(body) => body.is_end_stream(),
+        }
+    }
+}
+
+fn is_lz4_block(headers: &HeaderMap) -> bool {
+    headers.get(CONTENT_ENCODING).is_some_and(|value| {
+        value
+            .as_bytes()
+            .eq_ignore_ascii_case(LZ4_BLOCK_ENCODING.as_bytes())
+    })
+}
+
+fn has_body(status: StatusCode) -> bool {
+    !(status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED)
+}
+
+/// Tell if the parameters of a coding from `Accept-Encoding` make it acceptable.
+///
+/// A zero quality means "not acceptable", and so does an invalid one.
+fn is_acceptable<'a>(mut params: impl Iterator<Item = &'a str>) -> bool {
+    params.all(|param| match param.split_once('=') {
+        Some((name, value)) if name.trim().eq_ignore_ascii_case("q") => value
+            .trim()
+            .parse::<f32>()
+            .is_ok_and(|quality| quality > 0.0 && quality <= 1.0),
+        _ => true,
+    })
+}
+
+/// Tell if `Accept-Encoding` accepts [`LZ4_BLOCK_ENCODING`], by name or with `*`.
+///
+/// The coding listed by name wins over `*`, so `lz4-block;q=0, *` refuses it.
+fn accepts_lz4_block(headers: &HeaderMap) -> bool {
+    let mut wildcard = false;
+    let codings = headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for coding in codings {
+        let mut params = coding.split(';').map(str::trim);
+        match params.next() {
+            Some(name) if name.eq_ignore_ascii_case(LZ4_BLOCK_ENCODING) => {
+                return is_acceptable(params)
+            }
+            Some("*") => wildcard = is_acceptable(params),
+            _ => {}
+        }
+    }
+    wildcard
+}
+
+/// Add `Accept-Encoding` to the `Vary` header, unless it is already there or `Vary` is `*`.
+fn add_vary_accept_encoding(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|name| name == "*" || name.eq_ignore_ascii_case(ACCEPT_ENCODING.as_str()));
+    if !listed {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_layer {
+    use super::{
+        accepts_lz4_block, add_vary_accept_encoding, Lz4BlockLayer, Lz4BlockMaybeBody,
+        LZ4_BLOCK_ENCODING,
+    };
+    use crate::lz4_block_body::test_lz4_block_body::{collect, noop_waker, Frames};
+    use crate::lz4_block_header::data::{VALID_DATA, VALID_EMPTY};
+
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, VARY};
+    use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use std::convert::Infallible;
+    use std::future::{ready, Future, Ready};
+    use std::pin::pin;
+    use std::task::{Context, Poll};
+
+    /// A service sending back the body of the request, with the given status.
+    struct Echo(StatusCode);
+
+    impl Service<Request<Lz4BlockMaybeBody<Frames>>> for Echo {
+        type Response = Response<Lz4BlockMaybeBody<Frames>>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<Lz4BlockMaybeBody<Frames>>) -> Self::Future {
+            let mut res = Response::new(req.into_body());
+            *res.status_mut() = self.0;
+            ready(Ok(res))
+        }
+    }
+
+    fn echo(content_encoding: bool, accept_encoding: &'static str) -> (HeaderMap, Vec<u8>) {
+        echo_with(
+            Method::POST,
+            StatusCode::OK,
+            content_encoding,
+            accept_encoding,
+        )
+    }
+
+    fn echo_with(
+        method: Method,
+        status: StatusCode,
+        content_encoding: bool,
+        accept_encoding: &'static str,
+    ) -> (HeaderMap, Vec<u8>) {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        let mut req = Request::new(Frames::new(vec![input], None));
+        *req.method_mut() = method;
+        if content_encoding {
+            req.headers_mut().insert(
+                CONTENT_ENCODING,
+                HeaderValue::from_static(LZ4_BLOCK_ENCODING),
+            );
+        }
+        req.headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let future = pin!(Lz4BlockLayer.layer(Echo(status)).call(req));
+        let res = match future.poll(&mut cx) {
+            Poll::Ready(res) => res.unwrap(),
+            Poll::Pending => panic!("the response should be ready"),
+        };
+        let (parts, body) = res.into_parts();
+        (parts.headers, collect(body).unwrap().0)
+    }
+
+    #[test]
+    fn decode_request() {
+        let (headers, body) = echo(true, "gzip");
+        assert_eq!(headers.get(CONTENT_ENCODING), None);
+        assert_eq!(body, b"...");
+    }
+
+    #[test]
+    fn encode_response() {
+        let (headers, body) = echo(true, "gzip, lz4-block");
+        assert_eq!(headers[CONTENT_ENCODING], LZ4_BLOCK_ENCODING);
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        // the default block size has the compression level 6
+        expected[8] |= 6;
+        expected[VALID_DATA.len() + 8] |= 6;
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn no_body() {
+        for (method, status) in [
+            (Method::HEAD, StatusCode::OK),
+            (Method::GET, StatusCode::NO_CONTENT),
+            (Method::GET, StatusCode::NOT_MODIFIED),
+        ] {
+            let (headers, _) = echo_with(method, status, false, "lz4-block");
+            assert_eq!(headers.get(CONTENT_ENCODING), None);
+        }
+    }
+
+    #[test]
+    fn plain() {
+        let (headers, body) = echo(false, "gzip");
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(headers.get(CONTENT_ENCODING), None);
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let accepts = |value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+            accepts_lz4_block(&headers)
+        };
+        assert!(accepts("lz4-block"));
+        assert!(accepts("gzip;q=0.5, LZ4-Block;q=1"));
+        assert!(!accepts("gzip"));
+        assert!(accepts("lz4-block; q=0.001"));
+        assert!(!accepts("lz4-block;q=0"));
+        assert!(!accepts("lz4-block;q=0.0000"));
+        assert!(!accepts("lz4-block; Q = 0"));
+        assert!(!accepts("lz4-block;q=abc"));
+        assert!(accepts("gzip, *"));
+        assert!(accepts("*;q=0.5"));
+        assert!(!accepts("*;q=0"));
+        assert!(!accepts("lz4-block;q=0, *"));
+        assert!(!accepts("*, lz4-block;q=0"));
+        assert!(accepts("*;q=0, lz4-block"));
+    }
+
+    #[test]
+    fn vary() {
+        let vary = |headers: &HeaderMap| {
+            headers
+                .get_all(VARY)
+                .iter()
+                .map(|value| value.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        // the response depends on Accept-Encoding, whether it is compressed or not
+        assert_eq!(vary(&echo(false, "lz4-block").0), ["accept-encoding"]);
+        assert_eq!(vary(&echo(false, "gzip").0), ["accept-encoding"]);
+        let (headers, _) = echo_with(Method::HEAD, StatusCode::OK, false, "lz4-block");
+        assert!(vary(&headers).is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(VARY, HeaderValue::from_static("Origin"));
+        add_vary_accept_encoding(&mut headers);
+        assert_eq!(vary(&headers), ["Origin", "accept-encoding"]);
+        add_vary_accept_encoding(&mut headers);
+        assert_eq!(vary(&headers), ["Origin", "accept-encoding"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(VARY, HeaderValue::from_static("*"));
+        add_vary_accept_encoding(&mut headers);
+        assert_eq!(vary(&headers), ["*"]);
+    }
+}
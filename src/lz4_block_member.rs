@@ -0,0 +1,499 @@
+use crate::block_decoder::BlockDecoder;
+use crate::common::{Checksum, ErrorStreamHash, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{Lz4BlockHeader, HEADER_LENGTH};
+use crate::lz4_block_input::ensure_vec;
+use crate::lz4_block_output::{Lz4BlockOutput, Lz4BlockOutputBase};
+use crate::stats::TRAILER_LENGTH;
+
+use twox_hash::XxHash64;
+
+use std::hash::Hasher;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+/// Wrapper around a [`Write`] object writing several members, each one ending with an empty block.
+///
+/// Each member can be read by a new Java `LZ4BlockInputStream`, or by [`MemberReader`].
+/// The data can only be written between [`Self::begin_member()`] and [`Self::end_member()`].
+/// The current member is ended when the [`MemberWriter`] is dropped.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{MemberReader, MemberWriter};
+/// use std::io::Write;
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut archive = Vec::new();
+///     let mut writer = MemberWriter::new(&mut archive)?;
+///     for record in ["first", "second"] {
+///         writer.begin_member()?;
+///         writer.write_all(record.as_bytes())?;
+///         println!("{:?}", writer.end_member()?);
+///     }
+///     writer.finish()?;
+///
+///     for member in MemberReader::new(&archive[..]) {
+///         let member = member?;
+///         println!("{:?} {:?}", member.compressed, member.data);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub type MemberWriter<'a, W> = MemberWriterBase<'a, W, Context>;
+
+impl<'a, W: Write> MemberWriter<'a, W> {
+    /// Create a new [`MemberWriter`] with the default [`Context`] and block size.
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(w: &'a mut W) -> std::io::Result<Self> {
+        Self::with_context(
+            w,
+            Context::default(),
+            Lz4BlockOutput::<Vec<u8>>::default_block_size(),
+        )
+    }
+}
+
+/// Wrapper around a [`Write`] object writing several members, each one ending with an empty block.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`MemberWriter`].
+#[derive(Debug)]
+pub struct MemberWriterBase<'a, W: Write, C: Compression> {
+    output: Lz4BlockOutputBase<'a, W, C>,
+    member_start: Option<u64>,
+}
+
+impl<'a, W: Write, C: Compression> MemberWriterBase<'a, W, C> {
+    /// Create a new [`MemberWriterBase`] with the default checksum implementation.
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    pub fn with_context(w: &'a mut W, c: C, block_size: usize) -> std::io::Result<Self> {
+        Ok(Self {
+            output: Lz4BlockOutputBase::with_context(w, c, block_size)?,
+            member_start: None,
+        })
+    }
+
+    /// Start a new member at the current position.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error of kind [`InvalidInput`](std::io::ErrorKind::InvalidInput) if a member is already started.
+    pub fn begin_member(&mut self) -> std::io::Result<()> {
+        if self.member_start.is_some() {
+            return Err(misuse_error("a member is already started"));
+        }
+        self.member_start = Some(self.output.offset());
+        Ok(())
+    }
+
+    /// Write the last block and the empty block of the current member.
+    ///
+    /// It returns the range of the member in the compressed output, empty block included.
+    /// See [`Lz4BlockOutputBase::write_end()`](crate::Lz4BlockOutputBase::write_end)
+    ///
+    /// # Errors
+    ///
+    /// It will return an error of kind [`InvalidInput`](std::io::ErrorKind::InvalidInput) if no member is started,
+    /// or an error if the blocks could not be written.
+    pub fn end_member(&mut self) -> std::io::Result<Range<u64>> {
+        let start = match self.member_start.take() {
+            Some(start) => start,
+            None => return Err(misuse_error("no member is started")),
+        };
+        self.output.write_end()?;
+        Ok(start..self.output.offset())
+    }
+
+    /// Get the number of compressed bytes written so far.
+    ///
+    /// The data of the current block is only counted once its block is written.
+    pub fn offset(&self) -> u64 {
+        self.output.offset()
+    }
+
+    /// End the current member if any, and flush the wrapped writer.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the blocks could not be written.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if self.member_start.is_some() {
+            self.end_member()?;
+        }
+        self.output.flush()
+    }
+}
+
+impl<'a, W: Write, C: Compression> Write for MemberWriterBase<'a, W, C> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.member_start.is_none() {
+            return Err(misuse_error("no member is started"));
+        }
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<'a, W: Write, C: Compression> Drop for MemberWriterBase<'a, W, C> {
+    fn drop(&mut self) {
+        if self.member_start.is_some() {
+            let _ = self.end_member();
+        }
+    }
+}
+
+fn misuse_error(msg: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidInput, msg)
+}
+
+/// A member given by [`MemberReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// The decompressed data of the member
+    pub data: Vec<u8>,
+    /// The range of the member in the compressed input, empty block included
+    pub compressed: Range<u64>,
+}
+
+/// Iterator over the members of a [`Read`] object, each one ending with an empty block.
+///
+/// Each member is decompressed entirely in memory.
+/// The input may end without an empty block after the last member, like the streams read by
+/// [`Lz4BlockInput`](crate::Lz4BlockInput).
+/// After an error, it returns nothing more.
+pub type MemberReader<R> = MemberReaderBase<R, Context>;
+
+impl<R: Read> MemberReader<R> {
+    /// Create a new [`MemberReader`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(r: R) -> Self {
+        Self::with_context(r, Context::default())
+    }
+}
+
+/// Iterator over the members of a [`Read`] object, each one ending with an empty block.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`MemberReader`].
+#[derive(Debug)]
+pub struct MemberReaderBase<R: Read, C: Compression> {
+    reader: R,
+    decoder: BlockDecoder<C>,
+    compressed_buf: Vec<u8>,
+    offset: u64,
+    finished: bool,
+    xxh64_trailer: bool,
+}
+
+impl<R: Read, C: Compression> MemberReaderBase<R, C> {
+    /// Create a new [`MemberReaderBase`] with the default checksum implementation.
+    pub fn with_context(r: R, c: C) -> Self {
+        Self {
+            reader: r,
+            decoder: BlockDecoder::new(c, Checksum::new(Lz4BlockHeader::default_checksum)),
+            compressed_buf: Vec::new(),
+            offset: 0,
+            finished: false,
+            xxh64_trailer: false,
+        }
+    }
+
+    /// Check the XXH64 trailer following the empty block of each member, as written by
+    /// [`Lz4BlockOutputBase::set_xxh64_trailer()`](crate::Lz4BlockOutputBase::set_xxh64_trailer).
+    ///
+    /// Each member must then end with its empty block and trailer.
+    ///
+    /// **Beware**: the streams written by the Java implementation have no trailer, so they are rejected with this option.
+    pub fn set_xxh64_trailer(&mut self, xxh64_trailer: bool) {
+        self.xxh64_trailer = xxh64_trailer;
+    }
+
+    /// Give back the wrapped reader, positioned after the last member returned.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn read_member(&mut self) -> Result<Option<Member>> {
+        let start = self.offset;
+        let mut data = Vec::new();
+        loop {
+            let header = match Lz4BlockHeader::read(&mut self.reader)? {
+                None if self.offset == start => return Ok(None),
+                None if self.xxh64_trailer => {
+                    return Err(IoError::new(
+                        IoErrorKind::UnexpectedEof,
+                        "the member ends without its XXH64 trailer",
+                    )
+                    .into())
+                }
+                None => break,
+                Some(h) => h,
+            };
+            self.offset += HEADER_LENGTH as u64;
+            if header.decompressed_len == 0 {
+                if self.xxh64_trailer {
+                    self.read_trailer(&data)?;
+                }
+                break;
+            }
+            self.decoder.check_compressed_len(&header)?;
+            ensure_vec(
+                &mut self.compressed_buf,
+                self.decoder.get_maximum_compressed_buffer_len(
+                    header.compression_level.get_max_decompressed_buffer_len(),
+                ),
+                header.compressed_len,
+            );
+            self.reader.read_exact(&mut self.compressed_buf)?;
+            self.offset += header.compressed_len as u64;
+            let block_start = data.len();
+            data.resize(block_start + header.decompressed_len as usize, 0);
+            self.decoder
+                .decode_into(&header, &self.compressed_buf, &mut data[block_start..])?;
+        }
+        Ok(Some(Member {
+            data,
+            compressed: start..self.offset,
+        }))
+    }
+
+    fn read_trailer(&mut self, data: &[u8]) -> Result<()> {
+        let mut trailer = [0u8; TRAILER_LENGTH];
+        self.reader.read_exact(&mut trailer)?;
+        self.offset += trailer.len() as u64;
+        let trailer_value = u64::from_le_bytes(trailer);
+        let mut stream_hash = XxHash64::with_seed(0);
+        stream_hash.write(data);
+        let computed_value = stream_hash.finish();
+        if trailer_value != computed_value {
+            return ErrorStreamHash::new_error(trailer_value, computed_value);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, C: Compression> Iterator for MemberReaderBase<R, C> {
+    type Item = std::io::Result<Member>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.read_member() {
+            Ok(Some(member)) => Some(Ok(member)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_member {
+    use super::{Member, MemberReader, MemberReaderBase, MemberWriter, MemberWriterBase};
+    use crate::compression::{Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{ErrorKind, Write};
+
+    #[test]
+    fn write_basic() {
+        let mut out = Vec::new();
+        let mut writer = MemberWriter::with_context(&mut out, Context::default(), 64).unwrap();
+        writer.begin_member().unwrap();
+        writer.write_all(b"...").unwrap();
+        assert_eq!(
+            writer.end_member().unwrap(),
+            0..(VALID_DATA.len() + HEADER_LENGTH) as u64
+        );
+        writer.begin_member().unwrap();
+        let range = writer.end_member().unwrap();
+        assert_eq!(range.end - range.start, HEADER_LENGTH as u64);
+
+        writer.finish().unwrap();
+
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn write_outside_member() {
+        let mut out = Vec::new();
+        let mut writer = MemberWriter::new(&mut out).unwrap();
+        assert_eq!(
+            writer.write_all(b"...").unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            writer.end_member().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        writer.begin_member().unwrap();
+        assert_eq!(
+            writer.begin_member().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn write_drop_ends_member() {
+        let mut out = Vec::new();
+        let mut writer = MemberWriter::with_context(&mut out, Context::default(), 64).unwrap();
+        writer.begin_member().unwrap();
+        writer.write_all(b"...").unwrap();
+        drop(writer);
+
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn round_trip() {
+        let first = mixed();
+        let mut archive = Vec::new();
+        let mut writer = MemberWriter::with_context(&mut archive, Context::default(), 128).unwrap();
+        let mut ranges = Vec::new();
+        for data in [&first[..], b"", b"second"] {
+            writer.begin_member().unwrap();
+            writer.write_all(data).unwrap();
+            ranges.push(writer.end_member().unwrap());
+        }
+        writer.finish().unwrap();
+
+        let members: Vec<Member> = MemberReader::new(&archive[..])
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            members,
+            [
+                Member {
+                    data: first,
+                    compressed: ranges[0].clone(),
+                },
+                Member {
+                    data: Vec::new(),
+                    compressed: ranges[1].clone(),
+                },
+                Member {
+                    data: b"second".to_vec(),
+                    compressed: ranges[2].clone(),
+                },
+            ]
+        );
+        assert_eq!(ranges[2].end, archive.len() as u64);
+    }
+
+    #[test]
+    fn read_without_empty_block() {
+        let mut reader = MemberReader::new(&VALID_DATA[..]);
+        assert_eq!(
+            reader.next().unwrap().unwrap(),
+            Member {
+                data: b"...".to_vec(),
+                compressed: 0..VALID_DATA.len() as u64,
+            }
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn read_with_trailer() {
+        let mut archive = Vec::<u8>::new();
+        let mut writer =
+            Lz4BlockOutput::with_context(&mut archive, Context::default(), 64).unwrap();
+        writer.set_xxh64_trailer(true);
+        for data in [&b"first"[..], b"", b"second"] {
+            writer.write_all(data).unwrap();
+            writer.write_end().unwrap();
+        }
+        drop(writer);
+
+        let read = |archive: &[u8]| {
+            let mut reader = MemberReader::new(archive);
+            reader.set_xxh64_trailer(true);
+            reader.collect::<std::io::Result<Vec<Member>>>()
+        };
+        let members = read(&archive).unwrap();
+        assert_eq!(
+            members.iter().map(|m| &m.data[..]).collect::<Vec<_>>(),
+            [&b"first"[..], b"", b"second"]
+        );
+        // the raw blocks are followed by an empty block and a trailer
+        assert_eq!(
+            members.iter().map(|m| m.compressed.end).collect::<Vec<_>>(),
+            [55, 84, 140]
+        );
+        assert_eq!(archive.len(), 140);
+
+        // the trailer of the first member is read as the next header
+        assert!(MemberReader::new(&archive[..]).any(|m| m.is_err()));
+
+        *archive.last_mut().unwrap() ^= 1;
+        assert!(read(&archive).is_err());
+        archive.pop();
+        assert!(read(&archive).is_err());
+    }
+
+    #[test]
+    fn round_trip_context_pair() {
+        let data = mixed();
+        let mut out = Vec::new();
+        let mut writer =
+            MemberWriterBase::with_context(&mut out, ContextPair::default(), 128).unwrap();
+        for member in [&data[..], b"..."] {
+            writer.begin_member().unwrap();
+            writer.write_all(member).unwrap();
+            writer.end_member().unwrap();
+        }
+        drop(writer);
+
+        let members: Vec<Vec<u8>> =
+            MemberReaderBase::with_context(&out[..], ContextPair::default())
+                .map(|member| member.unwrap().data)
+                .collect();
+        assert_eq!(members, [data, b"...".to_vec()]);
+    }
+
+    #[test]
+    fn read_forged_compressed_len() {
+        let mut input = VALID_DATA.to_vec();
+        // a few bytes of data can't need 2 GB once compressed
+        input[9..13].copy_from_slice(&(i32::MAX as u32).to_le_bytes());
+        let mut reader = MemberReader::new(&input[..]);
+        let err = reader.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("compressed size"));
+        // the error comes before the compressed buffer is allocated
+        assert_eq!(reader.compressed_buf.capacity(), 0);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn read_invalid() {
+        let mut input = VALID_DATA.to_vec();
+        *input.last_mut().unwrap() = b'#';
+        let mut reader = MemberReader::new(&input[..]);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::block_decoder::BlockDecoder;
+use crate::block_encoder::BlockEncoder;
+use crate::common::{Checksum, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+use crate::lz4_block_output::Lz4BlockOutput;
+
+use std::convert::TryInto;
+
+/// Compress and decompress discrete messages, each one ending with an empty block.
+///
+/// A message is encoded like the Java `LZ4BlockOutputStream` writing it then being closed,
+/// so the peer can read it with a new `LZ4BlockInputStream`.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::MessageCodec;
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut codec = MessageCodec::default();
+///     let mut buf = codec.encode("first".as_bytes())?;
+///     buf.extend(codec.encode("second".as_bytes())?);
+///
+///     let (first, consumed) = codec.decode(&buf)?;
+///     let (second, _) = codec.decode(&buf[consumed..])?;
+///     println!("{:?} {:?}", first, second);
+///     Ok(())
+/// }
+/// ```
+pub type MessageCodec = MessageCodecBase<Context>;
+
+impl MessageCodec {
+    /// Create a new [`MessageCodec`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new(block_size: usize) -> std::io::Result<Self> {
+        Self::with_context(Context::default(), block_size)
+    }
+}
+
+/// Compress and decompress discrete messages, each one ending with an empty block.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`MessageCodec`].
+#[derive(Debug)]
+pub struct MessageCodecBase<C: Compression> {
+    encoder: BlockEncoder<C>,
+    decoder: BlockDecoder<C>,
+    compression_level: CompressionLevel,
+    block_size: usize,
+}
+
+impl<C: Compression> MessageCodecBase<C> {
+    /// Create a new [`MessageCodecBase`] with the default checksum implementation.
+    ///
+    /// The `block_size` must be between `64` and `33554432` bytes.
+    /// `c` is cloned, as the encoder and the decoder each keep one.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    pub fn with_context(c: C, block_size: usize) -> std::io::Result<Self>
+    where
+        C: Clone,
+    {
+        let compression_level = CompressionLevel::from_block_size(block_size)?;
+        Ok(Self {
+            decoder: BlockDecoder::new(c.clone(), Checksum::new(Lz4BlockHeader::default_checksum)),
+            encoder: BlockEncoder::new(
+                c,
+                compression_level,
+                Checksum::new(Lz4BlockHeader::default_checksum),
+            ),
+            compression_level,
+            block_size,
+        })
+    }
+
+    /// Compress `msg` into its blocks followed by the empty block.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block could not be compressed.
+    pub fn encode(&mut self, msg: &[u8]) -> std::io::Result<Vec<u8>> {
+        Ok(Self::encode_inner(self, msg)?)
+    }
+
+    fn encode_inner(&mut self, msg: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for block in msg.chunks(self.block_size) {
+            self.encoder.encode_into(block, &mut out)?;
+        }
+        Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        }
+        .write(&mut out)?;
+        Ok(out)
+    }
+
+    /// Decompress the first message of `buf`.
+    ///
+    /// It returns the message and the number of bytes it used in `buf`, including its empty block.
+    /// The blocks can have any size, not only the block size of this codec.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error of kind [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof)
+    /// if `buf` ends before the empty block, so the caller can try again once it received more data.
+    /// It will return an other error if a block is invalid.
+    pub fn decode(&self, buf: &[u8]) -> std::io::Result<(Vec<u8>, usize)> {
+        Ok(Self::decode_inner(self, buf)?)
+    }
+
+    fn decode_inner(&self, buf: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut msg = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let remaining = &buf[consumed..];
+            if remaining.len() < HEADER_LENGTH {
+                return Err(incomplete_error().into());
+            }
+            let header = Lz4BlockHeader::parse(remaining[..HEADER_LENGTH].try_into().unwrap())?;
+            consumed += HEADER_LENGTH;
+            if header.decompressed_len == 0 {
+                return Ok((msg, consumed));
+            }
+            self.decoder.check_compressed_len(&header)?;
+            let block_end = HEADER_LENGTH + header.compressed_len as usize;
+            if remaining.len() < block_end {
+                return Err(incomplete_error().into());
+            }
+            let block_start = msg.len();
+            msg.resize(block_start + header.decompressed_len as usize, 0);
+            self.decoder.decode_into(
+                &header,
+                &remaining[HEADER_LENGTH..block_end],
+                &mut msg[block_start..],
+            )?;
+            consumed += header.compressed_len as usize;
+        }
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self::new(Lz4BlockOutput::<Vec<u8>>::default_block_size()).unwrap()
+    }
+}
+
+fn incomplete_error() -> IoError {
+    IoError::new(
+        IoErrorKind::UnexpectedEof,
+        "the message does not end with an empty block",
+    )
+}
+
+#[cfg(test)]
+mod test_lz4_block_message {
+    use super::{MessageCodec, MessageCodecBase};
+    use crate::compression::{Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+    use crate::lz4_block_input::Lz4BlockInput;
+
+    use std::io::{ErrorKind, Read};
+
+    #[test]
+    fn encode_basic() {
+        let mut codec = MessageCodec::new(64).unwrap();
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(codec.encode(b"...").unwrap(), expected);
+        assert_eq!(codec.encode(b"").unwrap(), VALID_EMPTY);
+    }
+
+    #[test]
+    fn round_trip() {
+        let msg = mixed();
+        let mut codec = MessageCodec::with_context(Context::default(), 128).unwrap();
+        let mut buf = codec.encode(&msg).unwrap();
+        let first_len = buf.len();
+        buf.extend(codec.encode(b"second").unwrap());
+
+        let (first, consumed) = codec.decode(&buf).unwrap();
+        assert_eq!(first, msg);
+        assert_eq!(consumed, first_len);
+        assert_eq!(
+            codec.decode(&buf[consumed..]).unwrap(),
+            (b"second".to_vec(), buf.len() - first_len)
+        );
+    }
+
+    #[test]
+    fn round_trip_context_pair() {
+        let msg = mixed();
+        let mut codec = MessageCodecBase::with_context(ContextPair::default(), 128).unwrap();
+        let buf = codec.encode(&msg).unwrap();
+        assert_eq!(codec.decode(&buf).unwrap(), (msg, buf.len()));
+    }
+
+    #[test]
+    fn readable_by_lz4_block_input() {
+        let buf = MessageCodec::default().encode(b"...").unwrap();
+        let mut out = Vec::new();
+        Read::read_to_end(&mut Lz4BlockInput::new(&buf[..]), &mut out).unwrap();
+        assert_eq!(out, b"...");
+    }
+
+    #[test]
+    fn decode_incomplete() {
+        let codec = MessageCodec::default();
+        for buf in [
+            &VALID_DATA[..],
+            &VALID_DATA[..10],
+            &VALID_DATA[..HEADER_LENGTH + 1],
+        ] {
+            assert_eq!(
+                codec.decode(buf).unwrap_err().kind(),
+                ErrorKind::UnexpectedEof
+            );
+        }
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let mut buf = VALID_DATA.to_vec();
+        *buf.last_mut().unwrap() = b'#';
+        buf.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(
+            MessageCodec::default().decode(&buf).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
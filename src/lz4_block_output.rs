@@ -1,8 +1,16 @@
-use crate::common::{Checksum, ErrorInternal, Result};
+use crate::block_encoder::{BlockEncoder, BlockPipeline};
+use crate::block_strategy::BlockStrategy;
+use crate::common::{Checksum, ErrorInternal, IoError, IoErrorKind, Observer, Result};
 use crate::compression::{Compression, Context};
-use crate::lz4_block_header::{CompressionLevel, CompressionMethod, Lz4BlockHeader};
+use crate::lz4_block_header::{
+    BlockInfo, CompressionLevel, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH,
+};
+use crate::stats::TRAILER_LENGTH;
+
+use twox_hash::XxHash64;
 
 use std::cmp::min;
+use std::hash::Hasher;
 use std::io::Write;
 use std::result::Result as StdResult;
 
@@ -33,6 +41,80 @@ impl<'a, W: Write> Lz4BlockOutput<'a, W> {
     pub fn new(w: &'a mut W) -> Self {
         Self::with_context(w, Context::default(), Self::default_block_size()).unwrap()
     }
+
+    /// Create a new [`Lz4BlockOutput`] giving the same compressed bytes for the same data, whatever the feature flags.
+    ///
+    /// It uses [`Context::deterministic()`], the [`SmallestBlock`](crate::SmallestBlock) strategy and the given `block_size`.
+    /// See [`Context::deterministic()`] for the lz4_flex versions it holds for.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `block_size` is out of range
+    #[cfg(feature = "lz4_flex")]
+    pub fn deterministic(w: &'a mut W, block_size: usize) -> std::io::Result<Self> {
+        let mut output = Self::with_context(w, Context::deterministic(), block_size)?;
+        output.set_block_strategy(crate::block_strategy::SmallestBlock);
+        Ok(output)
+    }
+}
+
+/// Get the maximum number of bytes written by [`Lz4BlockOutputBase`] when compressing `input_len` bytes
+/// with the given [`Compression`] and `block_size` into a single member.
+///
+/// Each block costs a header. The data of a block is bounded by the bigger of its decompressed length
+/// and the compress bound of `c`, as a [`BlockStrategy`] may force LZ4 on incompressible data.
+/// It includes the empty block ending the stream and room for the trailer of
+/// [`Lz4BlockOutputBase::set_xxh64_trailer()`].
+///
+/// **Beware**: calling [`flush()`](Write::flush) in the middle of a block writes the pending data as a smaller block,
+/// which costs another header. Such blocks are not counted.
+///
+/// # Errors
+///
+/// It will return an error if the `block_size` is out of range, or if the result doesn't fit in a [`usize`]
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{max_compressed_len, Context, Lz4BlockOutput};
+/// use std::io::Write;
+///
+/// fn main() -> std::io::Result<()> {
+///     let data = "...".repeat(1000);
+///     let max_len = max_compressed_len(&Context::default(), data.len(), 1 << 16)?;
+///     let mut output = Vec::with_capacity(max_len);
+///     let mut writer = Lz4BlockOutput::new(&mut output);
+///     writer.write_all(data.as_bytes())?;
+///     writer.write_end()?;
+///     drop(writer);
+///     assert!(output.len() <= output.capacity());
+///     Ok(())
+/// }
+/// ```
+pub fn max_compressed_len<C: Compression>(
+    c: &C,
+    input_len: usize,
+    block_size: usize,
+) -> std::io::Result<usize> {
+    CompressionLevel::from_block_size(block_size)?;
+    let max_block_len = |len: usize| {
+        len.max(c.get_maximum_compressed_buffer_len(len))
+            .checked_add(HEADER_LENGTH)
+    };
+    let remaining = input_len % block_size;
+    let max_len = max_block_len(block_size)
+        .and_then(|max_block_len| max_block_len.checked_mul(input_len / block_size))
+        .and_then(|max_len| match remaining {
+            0 => Some(max_len),
+            _ => max_block_len(remaining)?.checked_add(max_len),
+        })
+        .and_then(|max_len| max_len.checked_add(HEADER_LENGTH + TRAILER_LENGTH));
+    max_len.ok_or_else(|| {
+        IoError::new(
+            IoErrorKind::InvalidInput,
+            "the maximum compressed length overflows",
+        )
+    })
 }
 
 /// Wrapper around a [`Write`] object to compress data.
@@ -41,12 +123,15 @@ impl<'a, W: Write> Lz4BlockOutput<'a, W> {
 #[derive(Debug)]
 pub struct Lz4BlockOutputBase<'a, W: Write + Sized, C: Compression> {
     writer: &'a mut W,
-    compression: C,
-    compression_level: CompressionLevel,
     write_ptr: usize,
     decompressed_buf: Vec<u8>,
-    compressed_buf: Vec<u8>,
-    checksum: Checksum,
+    encoder: Option<BlockEncoder<C>>,
+    pipeline: Option<BlockPipeline<C>>,
+    flush_inner: bool,
+    offset: u64,
+    observer: Option<Observer>,
+    compression_level: CompressionLevel,
+    stream_hash: Option<XxHash64>,
 }
 
 impl<'a, W: Write, C: Compression> Lz4BlockOutputBase<'a, W, C> {
@@ -79,19 +164,175 @@ impl<'a, W: Write, C: Compression> Lz4BlockOutputBase<'a, W, C> {
         checksum: fn(&[u8]) -> u32,
     ) -> std::io::Result<Self> {
         let compression_level = CompressionLevel::from_block_size(block_size)?;
-        let compressed_buf_len = c
-            .get_maximum_compressed_buffer_len(compression_level.get_max_decompressed_buffer_len());
         Ok(Self {
             writer: w,
-            compression: c,
-            compression_level,
             write_ptr: 0,
-            compressed_buf: vec![0u8; compressed_buf_len],
             decompressed_buf: vec![0u8; block_size],
-            checksum: Checksum::new(checksum),
+            encoder: Some(BlockEncoder::new(
+                c,
+                compression_level,
+                Checksum::new(checksum),
+            )),
+            pipeline: None,
+            flush_inner: true,
+            offset: 0,
+            observer: None,
+            compression_level,
+            stream_hash: None,
         })
     }
 
+    /// Set whether [`Write::flush()`] also flushes the wrapped [`Write`]. It does by default.
+    ///
+    /// Full blocks are written without flushing the wrapped [`Write`] either way.
+    pub fn set_flush_inner(&mut self, flush_inner: bool) {
+        self.flush_inner = flush_inner;
+    }
+
+    /// Set a function called after each block is written to the wrapped [`Write`].
+    ///
+    /// The [`BlockInfo::offset`] is the position of the block in the compressed stream.
+    pub fn set_observer<F: FnMut(&BlockInfo) + Send + 'static>(&mut self, observer: F) {
+        self.observer = Some(Observer::new(observer));
+    }
+
+    /// Set the [`BlockStrategy`] deciding how each block is stored.
+    ///
+    /// The default one is [`SmallestBlock`](crate::SmallestBlock).
+    pub fn set_block_strategy<S: BlockStrategy + 'static>(&mut self, strategy: S) {
+        if let Some(encoder) = &mut self.encoder {
+            encoder.set_strategy(Box::new(strategy));
+        } else if let Some(pipeline) = &mut self.pipeline {
+            // a stopped thread is reported when writing the next block
+            let _ = pipeline.set_strategy(Box::new(strategy));
+        }
+    }
+
+    /// Set whether the blocks are compressed on a background thread.
+    ///
+    /// When enabled, the next block is filled while the previous one is compressed.
+    /// The compressed blocks are still written to the wrapped [`Write`] from the calling thread.
+    /// Disabling it waits for the block being compressed and writes it.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the background thread stopped unexpectedly or the pending block could not be written.
+    pub fn set_pipelined(&mut self, pipelined: bool) -> std::io::Result<()>
+    where
+        C: Send + 'static,
+    {
+        if pipelined {
+            if let Some(encoder) = self.encoder.take() {
+                self.pipeline = Some(BlockPipeline::spawn(encoder));
+            }
+        } else if let Some(mut pipeline) = self.pipeline.take() {
+            let finished = pipeline.finish(self.writer);
+            self.encoder = Some(pipeline.stop()?);
+            if let Some(header) = finished? {
+                self.block_written(&header);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set whether [`Self::write_end()`] appends the XXH64 of the data written since the previous end.
+    ///
+    /// **Beware**: this is an extension of the format, only checked by a reader with
+    /// [`Lz4BlockInputBase::set_xxh64_trailer()`](crate::Lz4BlockInputBase::set_xxh64_trailer) enabled.
+    /// The Java implementation stops reading at the empty block, so it ignores the trailer of the last member only.
+    ///
+    /// It must be enabled before writing any data.
+    pub fn set_xxh64_trailer(&mut self, xxh64_trailer: bool) {
+        self.stream_hash = xxh64_trailer.then(|| XxHash64::with_seed(0));
+    }
+
+    /// Write the pending block followed by an empty block, like the Java implementation does when the stream is closed.
+    ///
+    /// If enabled by [`Self::set_xxh64_trailer()`], the empty block is followed by the XXH64 of the data
+    /// written since the previous end, as 8 little-endian bytes.
+    /// The wrapped [`Write`] is flushed according to [`Self::set_flush_inner()`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the blocks could not be compressed or written.
+    pub fn write_end(&mut self) -> std::io::Result<()> {
+        Ok(Self::write_end_inner(self)?)
+    }
+
+    fn write_end_inner(&mut self) -> Result<()> {
+        self.write_pending()?;
+        let header = Lz4BlockHeader {
+            compression_method: CompressionMethod::Raw,
+            compression_level: self.compression_level,
+            compressed_len: 0,
+            decompressed_len: 0,
+            checksum: 0,
+        };
+        header.write(self.writer)?;
+        self.block_written(&header);
+        if let Some(stream_hash) = &mut self.stream_hash {
+            self.writer.write_all(&stream_hash.finish().to_le_bytes())?;
+            self.offset += TRAILER_LENGTH as u64;
+            *stream_hash = XxHash64::with_seed(0);
+        }
+        if self.flush_inner {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Get the number of bytes written to the wrapped [`Write`], the blocks still buffered excluded.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    fn hash_written(&mut self, data: &[u8]) {
+        if let Some(stream_hash) = &mut self.stream_hash {
+            stream_hash.write(data);
+        }
+    }
+
+    /// Write all of `data`, compressing the full blocks directly from it.
+    ///
+    /// It gives the same result as [`Write::write_all()`], without copying the full blocks into the internal buffer.
+    /// The end of `data` which doesn't fill a block is kept in the internal buffer.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if a block could not be compressed or written.
+    pub fn write_all_blocks(&mut self, data: &[u8]) -> std::io::Result<()> {
+        Self::write_all_blocks_inner(self, data)?;
+        self.hash_written(data);
+        Ok(())
+    }
+
+    fn write_all_blocks_inner(&mut self, mut data: &[u8]) -> Result<()> {
+        let block_size = self.decompressed_buf.len();
+        if self.write_ptr > 0 {
+            let size_to_copy = min(data.len(), self.remaining_buf_len()?);
+            self.copy_to_buf(&data[..size_to_copy])?;
+            data = &data[size_to_copy..];
+            if self.write_ptr < block_size {
+                return Ok(());
+            }
+            self.write_block()?;
+        }
+        while data.len() >= block_size {
+            let header = match &mut self.encoder {
+                Some(encoder) => encoder.encode_into(&data[..block_size], self.writer)?,
+                // the pipeline needs its own copy of the block
+                None => break,
+            };
+            self.block_written(&header);
+            data = &data[block_size..];
+        }
+        while !data.is_empty() {
+            let written = self.write(data)?;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+
     fn copy_to_buf(&mut self, buf: &[u8]) -> StdResult<usize, ErrorInternal> {
         let buf_into = &mut self.decompressed_buf[self.write_ptr..];
         if buf.len() > buf_into.len() {
@@ -116,47 +357,73 @@ impl<'a, W: Write, C: Compression> Lz4BlockOutputBase<'a, W, C> {
 
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         if self.write_ptr == self.decompressed_buf.len() {
-            self.flush()?;
+            self.write_block()?;
+        }
+        let block_size = self.decompressed_buf.len();
+        if self.write_ptr == 0 && buf.len() >= block_size {
+            if let Some(encoder) = &mut self.encoder {
+                // compress directly from the caller's buffer
+                let header = encoder.encode_into(&buf[..block_size], self.writer)?;
+                self.block_written(&header);
+                return Ok(block_size);
+            }
         }
         let size_to_copy = min(buf.len(), self.remaining_buf_len()?);
         Ok(self.copy_to_buf(&buf[..size_to_copy])?)
     }
 
-    fn flush(&mut self) -> Result<()> {
+    fn write_block(&mut self) -> Result<()> {
         if self.write_ptr > 0 {
-            let decompressed_buf = &self.decompressed_buf[..self.write_ptr];
-            let compressed_buf = match self
-                .compression
-                .compress(decompressed_buf, self.compressed_buf.as_mut())
-            {
-                Ok(s) => &self.compressed_buf[..s],
-                Err(err) => return Err(err.into()),
+            let header = if let Some(encoder) = &mut self.encoder {
+                Some(encoder.encode_into(&self.decompressed_buf[..self.write_ptr], self.writer)?)
+            } else if let Some(pipeline) = &mut self.pipeline {
+                let header = pipeline.finish(self.writer)?;
+                pipeline.submit(&mut self.decompressed_buf, self.write_ptr)?;
+                header
+            } else {
+                return ErrorInternal::new_error("No block encoder available");
             };
-            let (compression_method, buf_to_write) =
-                if compressed_buf.len() < decompressed_buf.len() {
-                    (CompressionMethod::Lz4, compressed_buf)
-                } else {
-                    (CompressionMethod::Raw, decompressed_buf)
-                };
-            Lz4BlockHeader {
-                compression_method,
-                compression_level: self.compression_level,
-                compressed_len: buf_to_write.len() as u32,
-                decompressed_len: decompressed_buf.len() as u32,
-                checksum: self.checksum.run(decompressed_buf),
+            if let Some(header) = header {
+                self.block_written(&header);
             }
-            .write(&mut self.writer)?;
-            self.writer.write_all(buf_to_write)?;
         }
         self.write_ptr = 0;
-        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn block_written(&mut self, header: &Lz4BlockHeader) {
+        let block_info = header.block_info(self.offset);
+        self.offset += HEADER_LENGTH as u64 + header.compressed_len as u64;
+        if let Some(observer) = &mut self.observer {
+            observer.run(&block_info);
+        }
+    }
+
+    /// Write the pending block, and wait for the one being compressed in the background.
+    fn write_pending(&mut self) -> Result<()> {
+        self.write_block()?;
+        if let Some(pipeline) = &mut self.pipeline {
+            if let Some(header) = pipeline.finish(self.writer)? {
+                self.block_written(&header);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_pending()?;
+        if self.flush_inner {
+            self.writer.flush()?;
+        }
         Ok(())
     }
 }
 
 impl<'a, W: Write, C: Compression> Write for Lz4BlockOutputBase<'a, W, C> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        Ok(Self::write(self, buf)?)
+        let written = Self::write(self, buf)?;
+        self.hash_written(&buf[..written]);
+        Ok(written)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -172,18 +439,55 @@ impl<'a, W: Write, C: Compression> Drop for Lz4BlockOutputBase<'a, W, C> {
 
 #[cfg(test)]
 mod test_lz4_block_output {
-    use super::{CompressionLevel, Context, Lz4BlockOutput};
-    use crate::lz4_block_header::data::VALID_DATA;
+    use super::{max_compressed_len, CompressionLevel, Context, Lz4BlockOutput};
+    use crate::block_strategy::BlockStrategy;
+    use crate::lz4_block_header::data::{incompressible, mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::{BlockInfo, CompressionMethod, HEADER_LENGTH, TOKEN_INDEX};
+    use crate::stats::TRAILER_LENGTH;
+    use crate::Lz4BlockInput;
+
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Force(Option<CompressionMethod>);
+
+    impl BlockStrategy for Force {
+        fn select(&self, _block: &[u8]) -> Option<CompressionMethod> {
+            self.0
+        }
+    }
 
     use std::io::Write;
 
+    #[cfg(feature = "lz4_flex")]
+    fn golden_input() -> Vec<u8> {
+        let mut data = b"lz4-java-wrc ".repeat(20);
+        data.extend_from_slice(&incompressible(100));
+        data
+    }
+
+    /// [`golden_input()`] written by [`Lz4BlockOutput::deterministic()`] with 128 bytes blocks:
+    /// two LZ4 blocks and a raw block.
+    #[cfg(feature = "lz4_flex")]
+    const GOLDEN: [u8; 215] = [
+        76, 90, 52, 66, 108, 111, 99, 107, 32, 24, 0, 0, 0, 128, 0, 0, 0, 86, 185, 138, 12, 223,
+        108, 122, 52, 45, 106, 97, 118, 97, 45, 119, 114, 99, 32, 13, 0, 90, 96, 97, 118, 97, 45,
+        119, 114, 76, 90, 52, 66, 108, 111, 99, 107, 32, 24, 0, 0, 0, 128, 0, 0, 0, 220, 191, 48,
+        11, 223, 99, 32, 108, 122, 52, 45, 106, 97, 118, 97, 45, 119, 114, 13, 0, 90, 96, 45, 106,
+        97, 118, 97, 45, 76, 90, 52, 66, 108, 111, 99, 107, 16, 104, 0, 0, 0, 104, 0, 0, 0, 209,
+        202, 28, 13, 119, 114, 99, 32, 58, 171, 172, 38, 175, 35, 26, 113, 108, 145, 93, 49, 24,
+        62, 188, 210, 239, 81, 34, 157, 114, 79, 219, 217, 111, 57, 110, 174, 43, 200, 34, 47, 12,
+        227, 237, 140, 104, 123, 162, 137, 153, 214, 57, 167, 159, 242, 85, 254, 145, 21, 184, 32,
+        170, 122, 148, 138, 160, 77, 192, 157, 254, 73, 76, 220, 142, 224, 185, 6, 178, 48, 41, 74,
+        96, 28, 223, 60, 183, 98, 207, 66, 5, 25, 12, 75, 179, 223, 225, 124, 69, 251, 80, 81, 103,
+        112, 120, 201, 4, 248, 67, 12,
+    ];
+
     #[test]
     fn valid_default_block_size() {
         let default_block_size = Lz4BlockOutput::<Vec<u8>>::default_block_size();
-        assert_eq!(
-            CompressionLevel::from_block_size(default_block_size).is_ok(),
-            true
-        );
+        assert!(CompressionLevel::from_block_size(default_block_size).is_ok());
     }
 
     #[test]
@@ -203,10 +507,35 @@ mod test_lz4_block_output {
         assert_eq!(out, VALID_DATA);
     }
 
+    #[cfg(feature = "lz4_flex")]
+    #[test]
+    fn write_deterministic() {
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer = Lz4BlockOutput::deterministic(&mut out, 128).unwrap();
+            writer.set_pipelined(true).unwrap();
+            writer.write_all(&golden_input()).unwrap();
+        }
+        assert_eq!(out, GOLDEN);
+        assert!(Lz4BlockOutput::deterministic(&mut out, 0).is_err());
+    }
+
+    #[test]
+    fn write_end() {
+        let mut out = Vec::<u8>::new();
+        let mut writer = Lz4BlockOutput::with_context(&mut out, Context::default(), 64).unwrap();
+        writer.write_all("...".as_bytes()).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+        let mut expected = VALID_DATA.to_vec();
+        expected.extend_from_slice(&VALID_EMPTY);
+        assert_eq!(out, expected);
+    }
+
     #[test]
     fn write_several_small_blocks() {
         let mut out = Vec::<u8>::new();
-        let buf = ['.' as u8; 1024];
+        let buf = [b'.'; 1024];
         let loops = 1024;
         {
             let mut writer =
@@ -229,7 +558,7 @@ mod test_lz4_block_output {
     #[test]
     fn write_several_big_blocks() {
         let mut out = Vec::<u8>::new();
-        let buf = ['.' as u8; 128];
+        let buf = [b'.'; 128];
         let loops = 1234;
         {
             let mut writer =
@@ -262,4 +591,244 @@ mod test_lz4_block_output {
         expected.extend_from_slice(&VALID_DATA[..]);
         assert_eq!(out, expected);
     }
+
+    #[test]
+    fn max_compressed_len_empty() {
+        assert_eq!(
+            max_compressed_len(&Context::default(), 0, 128).unwrap(),
+            VALID_EMPTY.len() + TRAILER_LENGTH
+        );
+    }
+
+    #[test]
+    fn max_compressed_len_invalid_block_size() {
+        assert!(max_compressed_len(&Context::default(), 128, 0).is_err());
+    }
+
+    #[test]
+    fn max_compressed_len_overflow() {
+        let block_size = Lz4BlockOutput::<Vec<u8>>::default_block_size();
+        assert!(max_compressed_len(&Context::default(), usize::MAX, block_size).is_err());
+        assert!(max_compressed_len(&Context::default(), usize::MAX / 2, block_size).is_ok());
+    }
+
+    #[test]
+    fn max_compressed_len_incompressible() {
+        let block_size = 128;
+        let buf = incompressible(block_size * 10 + 3);
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), block_size).unwrap();
+            writer.set_block_strategy(Force(Some(CompressionMethod::Lz4)));
+            writer.set_xxh64_trailer(true);
+            writer.write_all(&buf).unwrap();
+            writer.write_end().unwrap();
+        }
+        let max_len = max_compressed_len(&Context::default(), buf.len(), block_size).unwrap();
+        assert!(max_len > buf.len() + 12 * HEADER_LENGTH + TRAILER_LENGTH);
+        assert!(out.len() <= max_len);
+    }
+
+    #[test]
+    fn block_strategy_force_raw() {
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_block_strategy(Force(Some(CompressionMethod::Raw)));
+            writer.write_all(&[b'.'; 128]).unwrap();
+        }
+        assert_eq!(out.len(), HEADER_LENGTH + 128);
+        assert_eq!(out[TOKEN_INDEX] & 0xf0, CompressionMethod::Raw.get_token());
+    }
+
+    #[test]
+    fn block_strategy_force_lz4() {
+        let buf = incompressible(128);
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_block_strategy(Force(Some(CompressionMethod::Lz4)));
+            writer.write_all(&buf).unwrap();
+        }
+        assert_eq!(out[TOKEN_INDEX] & 0xf0, CompressionMethod::Lz4.get_token());
+        let mut decompressed = Vec::new();
+        Lz4BlockInput::new(&out[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, buf);
+    }
+
+    #[derive(Default)]
+    struct CountFlush {
+        out: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for CountFlush {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.out.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flush_inner_full_blocks() {
+        let mut out = CountFlush::default();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            for _ in 0..10 {
+                writer.write_all(&[b'.'; 128]).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+        // explicit flush + drop
+        assert_eq!(out.flushes, 2);
+    }
+
+    #[test]
+    fn flush_inner_disabled() {
+        let mut out = CountFlush::default();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_flush_inner(false);
+            writer.write_all("...".as_bytes()).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(out.flushes, 0);
+        assert_eq!(out.out, VALID_DATA);
+    }
+
+    #[test]
+    fn pipelined_same_output() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut expected, Context::default(), 128).unwrap();
+            for i in 0..10 {
+                writer.write_all(&buf).unwrap();
+                if i == 5 {
+                    writer.flush().unwrap();
+                }
+            }
+        }
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_pipelined(true).unwrap();
+            for i in 0..10 {
+                writer.write_all(&buf).unwrap();
+                if i == 5 {
+                    writer.flush().unwrap();
+                }
+            }
+        }
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn pipelined_disable() {
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_pipelined(true).unwrap();
+            writer.write_all(&[b'.'; 1000]).unwrap();
+            writer.set_pipelined(false).unwrap();
+            writer.write_all(&[b'.'; 1000]).unwrap();
+        }
+        let mut decompressed = Vec::new();
+        Lz4BlockInput::new(&out[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, [b'.'; 2000]);
+    }
+
+    #[test]
+    fn write_bypass_buffer() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut expected, Context::default(), 128).unwrap();
+            for chunk in buf.chunks(7) {
+                writer.write_all(chunk).unwrap();
+            }
+        }
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.write_all(&buf[..5]).unwrap();
+            writer.write_all(&buf[5..]).unwrap();
+        }
+        assert_eq!(out, expected);
+    }
+
+    fn observed_blocks(pipelined: bool) -> Vec<BlockInfo> {
+        let blocks = Arc::new(Mutex::new(Vec::new()));
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_pipelined(pipelined).unwrap();
+            let observed = blocks.clone();
+            writer.set_observer(move |block| observed.lock().unwrap().push(*block));
+            writer.write_all(&incompressible(300)).unwrap();
+        }
+        let blocks = blocks.lock().unwrap().clone();
+        assert_eq!(
+            blocks.iter().map(|b| b.offset).collect::<Vec<_>>(),
+            [0, 149, 298]
+        );
+        assert_eq!(
+            blocks
+                .iter()
+                .map(|b| b.decompressed_len)
+                .collect::<Vec<_>>(),
+            [128, 128, 44]
+        );
+        blocks
+    }
+
+    #[test]
+    fn observer_blocks() {
+        assert_eq!(observed_blocks(false), observed_blocks(true));
+    }
+
+    fn written_with_all_blocks(pipelined: bool) -> Vec<u8> {
+        let buf = mixed();
+        let mut out = Vec::<u8>::new();
+        {
+            let mut writer =
+                Lz4BlockOutput::with_context(&mut out, Context::default(), 128).unwrap();
+            writer.set_pipelined(pipelined).unwrap();
+            writer.write_all_blocks(&buf[..5]).unwrap();
+            writer.write_all_blocks(&buf[5..123]).unwrap();
+            writer.write_all_blocks(&buf[123..]).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn write_all_blocks_same_output() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+        assert_eq!(written_with_all_blocks(false), expected);
+        assert_eq!(written_with_all_blocks(true), expected);
+    }
 }
@@ -0,0 +1,245 @@
+use crate::block_encoder::encode_block;
+use crate::block_strategy::SmallestBlock;
+use crate::common::{Checksum, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{CompressionLevel, Lz4BlockHeader};
+
+use std::cmp::min;
+use std::io::Write;
+
+/// Get the length of the compression buffer of [`Lz4BlockOutputFixed`] for the given block size.
+///
+/// It is big enough for all the backends.
+pub const fn fixed_compressed_buffer_len(block_size: usize) -> usize {
+    20 + block_size + block_size / 10 + 1
+}
+
+/// Wrapper around a [`Write`] object to compress data, using buffers stored inline.
+///
+/// The blocks hold `BLOCK` bytes and are compressed into a buffer of `COMPRESSED` bytes,
+/// which should be [`fixed_compressed_buffer_len()`] of `BLOCK`.
+///
+/// Unlike [`Lz4BlockOutput`](crate::Lz4BlockOutput), its buffers are stored inline,
+/// so it can live on the stack or in a `static`.
+///
+/// The blocks are compressed with LZ4 only if they get smaller, like the Java implementation.
+///
+/// **Beware**: the compression itself allocates nothing only with [`Context::Lz4Sys`](crate::Context),
+/// which keeps its hash table on the stack. `lz4_flex`, the default backend, allocates its hash table for each block
+/// and has no API to provide one, so this type does not avoid the heap entirely in the default build.
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{fixed_compressed_buffer_len, Lz4BlockOutputFixed};
+/// use std::io::Write;
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut output = Vec::new();
+///     Lz4BlockOutputFixed::<_, 1024, { fixed_compressed_buffer_len(1024) }>::new(&mut output)?
+///         .write_all("...".as_bytes())?;
+///     println!("{:?}", output);
+///     Ok(())
+/// }
+/// ```
+pub type Lz4BlockOutputFixed<'a, W, const BLOCK: usize, const COMPRESSED: usize> =
+    Lz4BlockOutputFixedBase<'a, W, Context, BLOCK, COMPRESSED>;
+
+impl<'a, W: Write, const BLOCK: usize, const COMPRESSED: usize>
+    Lz4BlockOutputFixed<'a, W, BLOCK, COMPRESSED>
+{
+    /// Create a new [`Lz4BlockOutputFixed`] with the default parameters.
+    ///
+    /// See [`Self::with_context()`]
+    #[inline]
+    pub fn new(w: &'a mut W) -> std::io::Result<Self> {
+        Self::with_context(w, Context::default())
+    }
+}
+
+/// Wrapper around a [`Write`] object to compress data, using buffers stored inline.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockOutputFixed`].
+#[derive(Debug)]
+pub struct Lz4BlockOutputFixedBase<
+    'a,
+    W: Write + Sized,
+    C: Compression,
+    const BLOCK: usize,
+    const COMPRESSED: usize,
+> {
+    writer: &'a mut W,
+    compression: C,
+    compression_level: CompressionLevel,
+    checksum: Checksum,
+    write_ptr: usize,
+    decompressed_buf: [u8; BLOCK],
+    compressed_buf: [u8; COMPRESSED],
+}
+
+impl<'a, W: Write, C: Compression, const BLOCK: usize, const COMPRESSED: usize>
+    Lz4BlockOutputFixedBase<'a, W, C, BLOCK, COMPRESSED>
+{
+    /// Create a new [`Lz4BlockOutputFixedBase`] with the default checksum implementation which is compatible with the Java's default implementation, including the missing 4 bits bug.
+    ///
+    /// See [`Self::with_checksum()`]
+    #[inline]
+    pub fn with_context(w: &'a mut W, c: C) -> std::io::Result<Self> {
+        Self::with_checksum(w, c, Lz4BlockHeader::default_checksum)
+    }
+
+    /// Create a new [`Lz4BlockOutputFixedBase`].
+    ///
+    /// The `BLOCK` size must be between `64` and `33554432` bytes.
+    /// The checksum must return a [`u32`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error if the `BLOCK` size is out of range,
+    /// or if `COMPRESSED` is too small for the compression library.
+    pub fn with_checksum(w: &'a mut W, c: C, checksum: fn(&[u8]) -> u32) -> std::io::Result<Self> {
+        if COMPRESSED < c.get_maximum_compressed_buffer_len(BLOCK) {
+            return Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                "the compression buffer is too small for the block size",
+            ));
+        }
+        Ok(Self {
+            writer: w,
+            compression: c,
+            compression_level: CompressionLevel::from_block_size(BLOCK)?,
+            checksum: Checksum::new(checksum),
+            write_ptr: 0,
+            decompressed_buf: [0u8; BLOCK],
+            compressed_buf: [0u8; COMPRESSED],
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.write_ptr == BLOCK {
+            self.write_block()?;
+        }
+        let size_to_copy = min(buf.len(), BLOCK - self.write_ptr);
+        self.decompressed_buf[self.write_ptr..self.write_ptr + size_to_copy]
+            .copy_from_slice(&buf[..size_to_copy]);
+        self.write_ptr += size_to_copy;
+        Ok(size_to_copy)
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.write_ptr > 0 {
+            let (header, buf_to_write) = encode_block(
+                &self.compression,
+                &SmallestBlock,
+                &self.checksum,
+                self.compression_level,
+                &self.decompressed_buf[..self.write_ptr],
+                &mut self.compressed_buf,
+            )?;
+            header.write(&mut self.writer)?;
+            self.writer.write_all(buf_to_write)?;
+        }
+        self.write_ptr = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_block()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a, W: Write, C: Compression, const BLOCK: usize, const COMPRESSED: usize> Write
+    for Lz4BlockOutputFixedBase<'a, W, C, BLOCK, COMPRESSED>
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(Self::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(Self::flush(self)?)
+    }
+}
+
+impl<'a, W: Write, C: Compression, const BLOCK: usize, const COMPRESSED: usize> Drop
+    for Lz4BlockOutputFixedBase<'a, W, C, BLOCK, COMPRESSED>
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_output_fixed {
+    use super::{fixed_compressed_buffer_len, Lz4BlockOutputFixed, Lz4BlockOutputFixedBase};
+    use crate::compression::{Compression, Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA};
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::Write;
+
+    #[test]
+    fn write_basic() {
+        let mut out = Vec::<u8>::new();
+        Lz4BlockOutputFixed::<_, 128, { fixed_compressed_buffer_len(128) }>::new(&mut out)
+            .unwrap()
+            .write_all("...".as_bytes())
+            .unwrap();
+        assert_eq!(out, VALID_DATA);
+    }
+
+    #[test]
+    fn write_invalid_block_size() {
+        let mut out = Vec::<u8>::new();
+        assert!(
+            Lz4BlockOutputFixed::<_, 32, { fixed_compressed_buffer_len(32) }>::new(&mut out)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn write_compressed_buffer_too_small() {
+        let mut out = Vec::<u8>::new();
+        assert!(Lz4BlockOutputFixed::<_, 128, 128>::new(&mut out).is_err());
+    }
+
+    #[test]
+    fn fixed_compressed_buffer_len_big_enough() {
+        for block_size in [64, 100, 1 << 16, (1 << 25) - 1, 1 << 25] {
+            assert!(
+                fixed_compressed_buffer_len(block_size)
+                    >= Context::default().get_maximum_compressed_buffer_len(block_size)
+            );
+        }
+    }
+
+    #[test]
+    fn write_with_compression() {
+        let mut out = Vec::<u8>::new();
+        Lz4BlockOutputFixedBase::<_, _, 128, { fixed_compressed_buffer_len(128) }>::with_context(
+            &mut out,
+            ContextPair::default(),
+        )
+        .unwrap()
+        .write_all("...".as_bytes())
+        .unwrap();
+        assert_eq!(out, VALID_DATA);
+    }
+
+    #[test]
+    fn write_same_as_lz4_block_output() {
+        let buf = mixed();
+        let mut expected = Vec::<u8>::new();
+        let mut out = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut expected, Context::default(), 128)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+        Lz4BlockOutputFixed::<_, 128, { fixed_compressed_buffer_len(128) }>::new(&mut out)
+            .unwrap()
+            .write_all(&buf)
+            .unwrap();
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::block_decoder::BlockDecoder;
+use crate::common::{Checksum, IoError, IoErrorKind, Result};
+use crate::compression::{Compression, Context};
+use crate::lz4_block_header::{Lz4BlockHeader, HEADER_LENGTH};
+
+use std::convert::TryInto;
+
+/// What happened while decoding the bytes given to [`Lz4BlockPushDecoder::push_bytes()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The data of a complete block was decompressed
+    Block(Vec<u8>),
+    /// An empty block ended the current member
+    EndOfMember,
+    /// All the complete blocks were decoded, more bytes are needed to go on
+    NeedMore,
+}
+
+/// Decompress the bytes pushed by the caller, without any I/O.
+///
+/// The bytes can be pushed in pieces of any size: the ones not forming a complete block yet are kept
+/// until the next call. The decoding goes on after the empty blocks, each one giving an [`Event::EndOfMember`].
+///
+/// # Example
+///
+/// ```rust
+/// use lz4_java_wrc::{Event, Lz4BlockPushDecoder};
+///
+/// const D: [u8; 24] = [
+///     76, 90, 52, 66, 108, 111, 99, 107, 16, 3, 0, 0, 0, 3, 0, 0, 0, 82, 228, 119, 6, 46, 46, 46,
+/// ];
+///
+/// fn main() -> std::io::Result<()> {
+///     let mut decoder = Lz4BlockPushDecoder::new();
+///     for chunk in D.chunks(5) {
+///         for event in decoder.push_bytes(chunk)? {
+///             if let Event::Block(data) = event {
+///                 println!("{:?}", data);
+///             }
+///         }
+///     }
+///     decoder.finish()
+/// }
+/// ```
+pub type Lz4BlockPushDecoder = Lz4BlockPushDecoderBase<Context>;
+
+impl Lz4BlockPushDecoder {
+    /// Create a new [`Lz4BlockPushDecoder`] with the default [`Context`].
+    ///
+    /// See [`Self::with_context()`]
+    pub fn new() -> Self {
+        Self::with_context(Context::default())
+    }
+}
+
+/// Decompress the bytes pushed by the caller, without any I/O.
+///
+/// Use this struct only if you want to provide your own Compression implementation. Otherwise use the alias [`Lz4BlockPushDecoder`].
+#[derive(Debug)]
+pub struct Lz4BlockPushDecoderBase<C: Compression> {
+    decoder: BlockDecoder<C>,
+    buf: Vec<u8>,
+}
+
+impl<C: Compression> Lz4BlockPushDecoderBase<C> {
+    /// Create a new [`Lz4BlockPushDecoderBase`] with the default checksum implementation.
+    pub fn with_context(c: C) -> Self {
+        Self {
+            decoder: BlockDecoder::new(c, Checksum::new(Lz4BlockHeader::default_checksum)),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Decode the blocks completed by `bytes`.
+    ///
+    /// The events are given in the order of the blocks, and the last one is always [`Event::NeedMore`].
+    ///
+    /// # Errors
+    ///
+    /// It will return an error at the first invalid block, including a header claiming more compressed data
+    /// than its decompressed length allows, before waiting for that data. The events of the blocks before it are lost,
+    /// and the invalid block stays first in the buffer, so the next calls fail the same way.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> std::io::Result<Vec<Event>> {
+        self.buf.extend_from_slice(bytes);
+        Ok(Self::decode(self)?)
+    }
+
+    fn decode(&mut self) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+        let mut consumed = 0;
+        // the blocks decoded before an error are dropped from the buffer too
+        let result = self.decode_blocks(&mut events, &mut consumed);
+        self.buf.drain(..consumed);
+        result?;
+        events.push(Event::NeedMore);
+        Ok(events)
+    }
+
+    fn decode_blocks(&self, events: &mut Vec<Event>, consumed: &mut usize) -> Result<()> {
+        loop {
+            let remaining = &self.buf[*consumed..];
+            if remaining.len() < HEADER_LENGTH {
+                return Ok(());
+            }
+            let header = Lz4BlockHeader::parse(remaining[..HEADER_LENGTH].try_into().unwrap())?;
+            self.decoder.check_compressed_len(&header)?;
+            let block_end = HEADER_LENGTH + header.compressed_len as usize;
+            if remaining.len() < block_end {
+                return Ok(());
+            }
+            if header.decompressed_len == 0 {
+                events.push(Event::EndOfMember);
+            } else {
+                let mut block = vec![0u8; header.decompressed_len as usize];
+                self.decoder.decode_into(
+                    &header,
+                    &remaining[HEADER_LENGTH..block_end],
+                    &mut block,
+                )?;
+                events.push(Event::Block(block));
+            }
+            *consumed += block_end;
+        }
+    }
+
+    /// Get the number of bytes kept until their block is complete.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Check that the input did not end in the middle of a block.
+    ///
+    /// # Errors
+    ///
+    /// It will return an error of kind [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof)
+    /// if some bytes are kept in the buffer.
+    pub fn finish(&self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(IoError::new(
+                IoErrorKind::UnexpectedEof,
+                "the input ends in the middle of a block",
+            ))
+        }
+    }
+}
+
+impl Default for Lz4BlockPushDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test_lz4_block_push_decoder {
+    use super::{Event, Lz4BlockPushDecoder, Lz4BlockPushDecoderBase};
+    use crate::compression::{Context, ContextPair};
+    use crate::lz4_block_header::data::{mixed, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{ErrorKind, Write};
+
+    #[test]
+    fn push_basic() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        let mut decoder = Lz4BlockPushDecoder::new();
+        assert_eq!(
+            decoder.push_bytes(&input).unwrap(),
+            [
+                Event::Block(b"...".to_vec()),
+                Event::EndOfMember,
+                Event::Block(b"...".to_vec()),
+                Event::NeedMore,
+            ]
+        );
+        assert!(decoder.finish().is_ok());
+    }
+
+    #[test]
+    fn push_context_pair() {
+        let data = mixed();
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        let mut decoder = Lz4BlockPushDecoderBase::with_context(ContextPair::default());
+        let mut out = Vec::new();
+        for event in decoder.push_bytes(&input).unwrap() {
+            if let Event::Block(block) = event {
+                out.extend_from_slice(&block);
+            }
+        }
+        assert_eq!(out, data);
+        assert!(decoder.finish().is_ok());
+    }
+
+    #[test]
+    fn push_byte_by_byte() {
+        let data = mixed();
+        let mut input = Vec::<u8>::new();
+        Lz4BlockOutput::with_context(&mut input, Context::default(), 128)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        input.extend_from_slice(&VALID_EMPTY);
+
+        let mut decoder = Lz4BlockPushDecoder::default();
+        let mut out = Vec::new();
+        let mut members = 0;
+        for byte in input.chunks(1) {
+            for event in decoder.push_bytes(byte).unwrap() {
+                match event {
+                    Event::Block(block) => out.extend_from_slice(&block),
+                    Event::EndOfMember => members += 1,
+                    Event::NeedMore => {}
+                }
+            }
+        }
+        assert_eq!(out, data);
+        assert_eq!(members, 1);
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn push_truncated() {
+        let mut decoder = Lz4BlockPushDecoder::new();
+        assert_eq!(
+            decoder.push_bytes(&VALID_DATA[..22]).unwrap(),
+            [Event::NeedMore]
+        );
+        assert_eq!(decoder.buffered_len(), 22);
+        assert_eq!(
+            decoder.finish().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn push_compressed_len_too_big() {
+        let mut header = VALID_DATA;
+        // an LZ4 block of 3 bytes claiming 1GiB of compressed data
+        header[8] = 0x20;
+        header[9..13].copy_from_slice(&(1u32 << 30).to_le_bytes());
+        let mut decoder = Lz4BlockPushDecoder::new();
+        let err = decoder.push_bytes(&header[..HEADER_LENGTH]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn push_invalid() {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_DATA);
+        *input.last_mut().unwrap() = b'#';
+        let mut decoder = Lz4BlockPushDecoder::new();
+        assert!(decoder.push_bytes(&input).is_err());
+        assert_eq!(decoder.buffered_len(), VALID_DATA.len());
+        assert!(decoder.push_bytes(&VALID_DATA).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::common::{IoError, IoErrorKind};
+use crate::compression::Context;
+use crate::lz4_block_header::{BlockInfo, CompressionMethod, Lz4BlockHeader, HEADER_LENGTH};
+use crate::lz4_block_input::{EmptyBlockPolicy, Lz4BlockInput};
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The length of the XXH64 trailer following each empty block, when enabled.
+pub(crate) const TRAILER_LENGTH: usize = 8;
+
+/// Summary of the blocks of a compressed stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// The number of blocks, including the empty ones
+    pub blocks: u64,
+    /// The number of blocks stored raw, excluding the empty ones
+    pub raw_blocks: u64,
+    /// The number of blocks compressed with LZ4
+    pub lz4_blocks: u64,
+    /// The number of empty blocks, which end a stream written by the Java implementation
+    pub empty_blocks: u64,
+    /// The length of the compressed stream, headers and XXH64 trailers included
+    pub compressed_len: u64,
+    /// The length of the data once decompressed
+    pub decompressed_len: u64,
+}
+
+impl StreamStats {
+    /// Get the compressed length divided by the decompressed length.
+    ///
+    /// It returns `0.0` if there is no data.
+    pub fn ratio(&self) -> f64 {
+        if self.decompressed_len == 0 {
+            0.0
+        } else {
+            self.compressed_len as f64 / self.decompressed_len as f64
+        }
+    }
+
+    pub(crate) fn add(&mut self, block: &BlockInfo) {
+        self.blocks += 1;
+        if block.decompressed_len == 0 {
+            self.empty_blocks += 1;
+        } else {
+            match block.compression_method {
+                CompressionMethod::Raw => self.raw_blocks += 1,
+                CompressionMethod::Lz4 => self.lz4_blocks += 1,
+            }
+        }
+        self.compressed_len += HEADER_LENGTH as u64 + block.compressed_len as u64;
+        self.decompressed_len += block.decompressed_len as u64;
+    }
+
+    pub(crate) fn add_trailer(&mut self) {
+        self.compressed_len += TRAILER_LENGTH as u64;
+    }
+}
+
+/// Decompress the whole stream and check every block, including its checksum.
+///
+/// The empty blocks are skipped, so concatenated streams are fully checked.
+/// A stream written with [`Lz4BlockOutputBase::set_xxh64_trailer()`](crate::Lz4BlockOutputBase::set_xxh64_trailer)
+/// is rejected, see [`verify_with_trailer()`].
+///
+/// # Errors
+///
+/// It will return an error at the first invalid block.
+pub fn verify<R: Read>(reader: R) -> io::Result<StreamStats> {
+    verify_inner(reader, false)
+}
+
+/// Decompress the whole stream like [`verify()`], checking the XXH64 trailer following each empty block.
+///
+/// # Errors
+///
+/// It will return an error at the first invalid block or trailer, or if the stream doesn't end with a trailer.
+pub fn verify_with_trailer<R: Read>(reader: R) -> io::Result<StreamStats> {
+    verify_inner(reader, true)
+}
+
+fn verify_inner<R: Read>(reader: R, xxh64_trailer: bool) -> io::Result<StreamStats> {
+    let mut input = Lz4BlockInput::with_checksum(
+        reader,
+        Context::default(),
+        Lz4BlockHeader::default_checksum,
+        EmptyBlockPolicy::SkipAll,
+    );
+    input.set_xxh64_trailer(xxh64_trailer);
+    io::copy(&mut input, &mut io::sink())?;
+    Ok(input.stats())
+}
+
+/// Read the headers of the whole stream, skipping the data of the blocks.
+///
+/// Nothing is decompressed, so the data and the checksums are not checked.
+/// A stream written with [`Lz4BlockOutputBase::set_xxh64_trailer()`](crate::Lz4BlockOutputBase::set_xxh64_trailer)
+/// is rejected, see [`stat_with_trailer()`].
+///
+/// # Errors
+///
+/// It will return an error at the first invalid header, or if the stream ends in the middle of a block.
+pub fn stat<R: Read>(reader: R) -> io::Result<StreamStats> {
+    stat_inner(reader, false)
+}
+
+/// Read the headers of the whole stream like [`stat()`], skipping the XXH64 trailer following each empty block.
+///
+/// The trailers are not checked.
+///
+/// # Errors
+///
+/// It will return an error at the first invalid header, or if the stream ends in the middle of a block or a trailer.
+pub fn stat_with_trailer<R: Read>(reader: R) -> io::Result<StreamStats> {
+    stat_inner(reader, true)
+}
+
+fn stat_inner<R: Read>(mut reader: R, xxh64_trailer: bool) -> io::Result<StreamStats> {
+    let mut stats = StreamStats::default();
+    while let Some(header) = Lz4BlockHeader::read(&mut reader)? {
+        let mut skipped_len = header.compressed_len as u64;
+        if xxh64_trailer && header.decompressed_len == 0 {
+            skipped_len += TRAILER_LENGTH as u64;
+        }
+        let skipped = io::copy(&mut (&mut reader).take(skipped_len), &mut io::sink())?;
+        if skipped != skipped_len {
+            return Err(truncated_error());
+        }
+        stats.add(&header.block_info(stats.compressed_len));
+        if skipped_len > header.compressed_len as u64 {
+            stats.add_trailer();
+        }
+    }
+    Ok(stats)
+}
+
+/// Read the headers of the whole stream, seeking over the data of the blocks.
+///
+/// It works like [`stat()`], without reading the data, so it takes about the same time whatever the size of the blocks.
+/// The stream is read from its current position.
+///
+/// # Errors
+///
+/// It will return an error at the first invalid header, or if the stream ends in the middle of a block.
+pub fn stat_seek<R: Read + Seek>(reader: R) -> io::Result<StreamStats> {
+    stat_seek_inner(reader, false)
+}
+
+/// Read the headers of the whole stream like [`stat_seek()`], seeking over the XXH64 trailer following each empty block.
+///
+/// The trailers are not checked.
+///
+/// # Errors
+///
+/// It will return an error at the first invalid header, or if the stream ends in the middle of a block or a trailer.
+pub fn stat_seek_with_trailer<R: Read + Seek>(reader: R) -> io::Result<StreamStats> {
+    stat_seek_inner(reader, true)
+}
+
+fn stat_seek_inner<R: Read + Seek>(mut reader: R, xxh64_trailer: bool) -> io::Result<StreamStats> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+    let mut stats = StreamStats::default();
+    while let Some(header) = Lz4BlockHeader::read(&mut reader)? {
+        let mut skipped_len = header.compressed_len as u64;
+        if xxh64_trailer && header.decompressed_len == 0 {
+            skipped_len += TRAILER_LENGTH as u64;
+        }
+        if start + stats.compressed_len + HEADER_LENGTH as u64 + skipped_len > end {
+            return Err(truncated_error());
+        }
+        reader.seek(SeekFrom::Current(skipped_len as i64))?;
+        stats.add(&header.block_info(stats.compressed_len));
+        if skipped_len > header.compressed_len as u64 {
+            stats.add_trailer();
+        }
+    }
+    Ok(stats)
+}
+
+fn truncated_error() -> IoError {
+    IoError::new(
+        IoErrorKind::UnexpectedEof,
+        "the stream ends in the middle of a block or a trailer",
+    )
+}
+
+#[cfg(test)]
+mod test_stats {
+    use super::{
+        stat, stat_seek, stat_seek_with_trailer, stat_with_trailer, verify, verify_with_trailer,
+        StreamStats,
+    };
+    use crate::compression::Context;
+    use crate::lz4_block_header::data::{incompressible, VALID_DATA, VALID_EMPTY};
+    use crate::lz4_block_header::HEADER_LENGTH;
+    use crate::lz4_block_output::Lz4BlockOutput;
+
+    use std::io::{Cursor, Read, Result, Seek, SeekFrom, Write};
+
+    fn input() -> Vec<u8> {
+        let mut input = VALID_DATA.to_vec();
+        input.extend_from_slice(&VALID_EMPTY);
+        input.extend_from_slice(&VALID_DATA);
+        input
+    }
+
+    const EXPECTED: StreamStats = StreamStats {
+        blocks: 3,
+        raw_blocks: 2,
+        lz4_blocks: 0,
+        empty_blocks: 1,
+        compressed_len: (VALID_DATA.len() * 2 + VALID_EMPTY.len()) as u64,
+        decompressed_len: 6,
+    };
+
+    #[test]
+    fn stat_basic() {
+        assert_eq!(stat(&input()[..]).unwrap(), EXPECTED);
+    }
+
+    #[test]
+    fn stat_truncated() {
+        let input = input();
+        assert!(stat(&input[..input.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn stat_seek_basic() {
+        let mut data = vec![b'#'; 5];
+        data.extend_from_slice(&input());
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(5);
+        assert_eq!(stat_seek(cursor).unwrap(), EXPECTED);
+    }
+
+    /// Count the bytes read, but not the ones sought over.
+    struct CountingReader<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = self.inner.read(buf)?;
+            self.read += len;
+            Ok(len)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn stat_seek_skips_data() {
+        let buf = incompressible(4096);
+        let mut input = Vec::new();
+        let mut writer =
+            Lz4BlockOutput::with_context(&mut input, Context::default(), 1024).unwrap();
+        writer.write_all(&buf).unwrap();
+        writer.write_end().unwrap();
+        drop(writer);
+
+        let mut reader = CountingReader {
+            inner: Cursor::new(&input[..]),
+            read: 0,
+        };
+        let stats = stat_seek(&mut reader).unwrap();
+        assert_eq!((stats.blocks, stats.decompressed_len), (5, 4096));
+        // only the headers are read
+        assert_eq!(reader.read, 5 * HEADER_LENGTH);
+    }
+
+    #[test]
+    fn stat_seek_truncated() {
+        let mut input = input();
+        input.pop();
+        assert!(stat_seek(Cursor::new(input)).is_err());
+    }
+
+    #[test]
+    fn verify_basic() {
+        assert_eq!(verify(&input()[..]).unwrap(), EXPECTED);
+    }
+
+    #[test]
+    fn verify_invalid_checksum() {
+        let mut input = input();
+        *input.last_mut().unwrap() = b'#';
+        assert!(stat(&input[..]).is_ok());
+        assert!(verify(&input[..]).is_err());
+    }
+
+    /// Two members, each one ending with an empty block and a trailer.
+    fn input_with_trailer() -> Vec<u8> {
+        let mut input = Vec::<u8>::new();
+        let mut writer = Lz4BlockOutput::with_context(&mut input, Context::default(), 64).unwrap();
+        writer.set_xxh64_trailer(true);
+        for _ in 0..2 {
+            writer.write_all(b"...").unwrap();
+            writer.write_end().unwrap();
+        }
+        drop(writer);
+        input
+    }
+
+    const EXPECTED_WITH_TRAILER: StreamStats = StreamStats {
+        blocks: 4,
+        raw_blocks: 2,
+        lz4_blocks: 0,
+        empty_blocks: 2,
+        compressed_len: ((VALID_DATA.len() + VALID_EMPTY.len() + 8) * 2) as u64,
+        decompressed_len: 6,
+    };
+
+    #[test]
+    fn stats_with_trailer() {
+        let input = input_with_trailer();
+        assert_eq!(input.len() as u64, EXPECTED_WITH_TRAILER.compressed_len);
+        assert_eq!(
+            stat_with_trailer(&input[..]).unwrap(),
+            EXPECTED_WITH_TRAILER
+        );
+        assert_eq!(
+            stat_seek_with_trailer(Cursor::new(&input)).unwrap(),
+            EXPECTED_WITH_TRAILER
+        );
+        assert_eq!(
+            verify_with_trailer(&input[..]).unwrap(),
+            EXPECTED_WITH_TRAILER
+        );
+
+        // the trailer of the first member is read as the next header
+        assert!(stat(&input[..]).is_err());
+        assert!(stat_seek(Cursor::new(&input)).is_err());
+        assert!(verify(&input[..]).is_err());
+    }
+
+    #[test]
+    fn stats_with_trailer_invalid() {
+        let mut input = input_with_trailer();
+        *input.last_mut().unwrap() ^= 1;
+        assert!(stat_with_trailer(&input[..]).is_ok());
+        assert!(verify_with_trailer(&input[..]).is_err());
+        input.pop();
+        assert!(stat_with_trailer(&input[..]).is_err());
+        assert!(stat_seek_with_trailer(Cursor::new(&input)).is_err());
+        assert!(verify_with_trailer(&input[..]).is_err());
+    }
+
+    #[test]
+    fn ratio() {
+        assert_eq!(StreamStats::default().ratio(), 0.0);
+        assert_eq!(EXPECTED.ratio(), 69.0 / 6.0);
+    }
+}